@@ -1,101 +1,1779 @@
-use crate::db;
-use crate::db::AssetRepository;
+use crate::auth::RequireScope;
+use crate::db::{AssetRepository, Scope};
+use crate::errors::MaskInternalErrors;
+use crate::metrics::GraphqlMetrics;
+use crate::options::{Mode, Role};
+use crate::persisted::{CACHE_SIZE, Manifest, PersistedQueryAllowlist};
+use crate::ratelimit::ImportQuota;
+use crate::{db, import};
+use anyhow::anyhow;
+use async_graphql::extensions::apollo_persisted_queries::{ApolloPersistedQueries, LruCacheStorage};
 use async_graphql::{
-    Context, Error, Object, OutputType, Result,
-    connection::{Connection, Edge, query},
+    Context, Enum, Error, ErrorExtensions, Guard, ID, InputObject, Interface, Json, Object, OutputType, Result,
+    Schema, SimpleObject, Subscription,
+    connection::{Connection, CursorType, Edge, query},
+    futures_util::{Stream, StreamExt, future, stream},
 };
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use tokio::sync::broadcast::error::RecvError;
+use serde::{Deserialize, Serialize};
 
-/// The maximum number of assets to return in a single query.
-const LIMIT: usize = 100;
+/// The number of assets returned by a connection when neither `first` nor `last` is given, at most the maximum page size.
+const DEFAULT_PAGE_SIZE: usize = 100;
 
-pub struct Asset(db::Asset);
+/// Limits on the cost of a single GraphQL operation.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// The deepest nesting of fields in an operation.
+    pub max_depth: usize,
+    /// The highest complexity of an operation, where connections cost their page size times the complexity of their nodes.
+    pub max_complexity: usize,
+    /// The largest `first` or `last` a connection accepts.
+    pub max_page_size: usize,
+    /// The most buckets a balance or net worth history may have.
+    pub max_history_points: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_depth: 12,
+            max_complexity: 2500,
+            max_page_size: 100,
+            max_history_points: 3660,
+        }
+    }
+}
+
+impl Limits {
+    /// The stricter limits used in production mode, unless overridden.
+    pub fn production() -> Self {
+        Self {
+            max_depth: 8,
+            max_complexity: 1000,
+            max_page_size: 50,
+            max_history_points: 1000,
+        }
+    }
+}
+
+pub type AssetSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+/// Builds the GraphQL schema, enforcing the given limits.
+///
+/// Queries are persisted automatically unless a manifest is given, in which case only queries from the manifest are executed.
+/// In production mode, introspection is disabled and internal error details are hidden from clients.
+/// Operations of other roles than the server's are rejected.
+pub fn build_schema(
+    asset_repository: AssetRepository,
+    limits: Limits,
+    manifest: Option<Manifest>,
+    mode: Mode,
+    role: Role,
+) -> AssetSchema {
+    let mut builder = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .limit_depth(limits.max_depth)
+        .limit_complexity(limits.max_complexity)
+        .data(asset_repository)
+        .data(limits)
+        .data(role)
+        .extension(GraphqlMetrics::new(
+            manifest.as_ref().map(Manifest::operation_names).unwrap_or_default(),
+        ));
+
+    if mode == Mode::Production {
+        builder = builder.disable_introspection().extension(MaskInternalErrors);
+    }
+
+    let builder = match manifest {
+        Some(manifest) => builder.extension(PersistedQueryAllowlist::new(manifest)),
+        None => builder.extension(ApolloPersistedQueries::new(LruCacheStorage::new(CACHE_SIZE))),
+    };
+
+    builder.finish()
+}
+
+/// Only resolves fields on servers that run the APIs of a role.
+pub struct ServedBy(pub Role);
+
+impl Guard for ServedBy {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        match ctx.data_opt::<Role>() {
+            Some(role) if !role.serves(self.0) => {
+                Err(Error::new(format!("This operation is served by {} instances, not {role} instances", self.0)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The schema as SDL. Limits, persisted queries and the mode don't change the types, so no repository is needed.
+pub fn sdl() -> String {
+    Schema::build(QueryRoot, MutationRoot, SubscriptionRoot).finish().sdl()
+}
+
+/// The complexity of a connection field, which is the complexity of a page of nodes plus the connection itself.
+fn connection_complexity(first: Option<i32>, last: Option<i32>, child_complexity: usize) -> usize {
+    let size = first
+        .or(last)
+        .map_or(DEFAULT_PAGE_SIZE, |size| size.max(0) as usize);
+
+    size.saturating_mul(child_complexity).saturating_add(1)
+}
+
+/// The number of nodes to return for a page, rejecting sizes above the maximum instead of silently capping them.
+fn page_size(first: Option<usize>, last: Option<usize>, limits: &Limits) -> Result<usize> {
+    match (first, last) {
+        (Some(first), _) if first > limits.max_page_size => Err(Error::new(format!(
+            "`first` must not exceed {}",
+            limits.max_page_size
+        ))),
+        (_, Some(last)) if last > limits.max_page_size => Err(Error::new(format!(
+            "`last` must not exceed {}",
+            limits.max_page_size
+        ))),
+        (Some(size), _) | (None, Some(size)) => Ok(size),
+        (None, None) => Ok(DEFAULT_PAGE_SIZE.min(limits.max_page_size)),
+    }
+}
+
+/// Rejects date ranges that are reversed or would be split into more buckets than the maximum.
+fn check_history_range(
+    from: chrono::DateTime<chrono::Utc>,
+    to: chrono::DateTime<chrono::Utc>,
+    interval: db::Interval,
+    limits: &Limits,
+) -> Result<()> {
+    if from > to {
+        return Err(Error::new("`from` must not be after `to`"));
+    }
+    if interval.bucket_count(from, to) > limits.max_history_points {
+        return Err(Error::new(format!(
+            "the range must not span more than {} intervals",
+            limits.max_history_points
+        )));
+    }
+
+    Ok(())
+}
+
+pub struct Asset {
+    snapshot: db::Asset,
+    /// The system time the snapshot was known at, which nested fields are answered as of.
+    known_at: DateTime<Utc>,
+}
+
+impl Asset {
+    fn new(snapshot: db::Asset, known_at: DateTime<Utc>) -> Self {
+        Self { snapshot, known_at }
+    }
+}
 
 /// An asset balance for a given account.
 #[Object]
 impl Asset {
+    /// The global id of the asset, which refetches its latest snapshot.
+    async fn id(&self) -> ID {
+        GlobalId::Asset {
+            identity_id: self.snapshot.identity_id.clone(),
+        }
+            .into()
+    }
+
+    /// The stable identity of the asset, which survives renames.
+    async fn identity_id(&self) -> &str {
+        &self.snapshot.identity_id
+    }
+
+    /// The id of the asset in the source system.
+    async fn asset_id(&self) -> &str {
+        &self.snapshot.asset_id
+    }
+
+    /// The wealth id of the asset in the source system.
+    async fn wid(&self) -> uuid::Uuid {
+        uuid::Uuid::from_u128(self.snapshot.wid as u128)
+    }
+
     /// The nickname of the asset.
     async fn nickname(&self) -> &str {
-        &self.0.nickname
+        &self.snapshot.nickname
     }
 
     /// The balance of the asset.
     async fn balance(&self) -> f64 {
-        self.0.balance_current
+        self.snapshot.balance_current
     }
 
     /// The datetime this balance for the asset was updated.
+    async fn balance_as_of(&self) -> &DateTime<Utc> {
+        &self.snapshot.balance_as_of
+    }
+
+    /// The cost basis of the balance, when known.
+    async fn cost_basis(&self) -> Option<f64> {
+        self.snapshot.balance_cost_basis
+    }
+
+    /// The primary category of the asset.
+    async fn category(&self) -> PrimaryAssetCategory {
+        PrimaryAssetCategory::from(&self.snapshot.primary_asset_category)
+    }
+
+    /// The primary category of the asset as it was imported.
+    async fn raw_category(&self) -> &str {
+        self.snapshot.primary_asset_category.name()
+    }
+
+    /// The type of wealth the asset represents.
+    async fn asset_type(&self) -> WealthAssetType {
+        WealthAssetType::from(&self.snapshot.wealth_asset_type)
+    }
+
+    /// The type of wealth the asset represents as it was imported.
+    async fn raw_asset_type(&self) -> &str {
+        self.snapshot.wealth_asset_type.name()
+    }
+
+    /// Whether the asset is an asset rather than a liability.
+    async fn is_asset(&self) -> bool {
+        self.snapshot.is_asset
+    }
+
+    /// Whether the asset is active.
+    async fn is_active(&self) -> bool {
+        self.snapshot.is_active
+    }
+
+    /// Whether the asset is marked as a favorite.
+    async fn is_favorite(&self) -> bool {
+        self.snapshot.is_favorite
+    }
+
+    /// Whether the asset counts towards net worth.
+    async fn include_in_net_worth(&self) -> bool {
+        self.snapshot.include_in_net_worth
+    }
+
+    /// The datetime the asset is scheduled to be deactivated by, if any.
+    async fn deactivate_by(&self) -> Option<&DateTime<Utc>> {
+        self.snapshot.deactivate_by.as_ref()
+    }
+
+    /// The datetime the asset was created in the source system.
+    async fn creation_date(&self) -> &DateTime<Utc> {
+        &self.snapshot.creation_date
+    }
+
+    /// The datetime the asset was last modified in the source system.
+    async fn modification_date(&self) -> &DateTime<Utc> {
+        &self.snapshot.modification_date
+    }
+
+    /// The datetime the balance was last updated successfully.
+    async fn last_update(&self) -> &DateTime<Utc> {
+        &self.snapshot.last_update
+    }
+
+    /// The datetime an update of the balance was last attempted.
+    async fn last_update_attempt(&self) -> &DateTime<Utc> {
+        &self.snapshot.last_update_attempt
+    }
+
+    /// The nicknames the asset has been known by, oldest first.
+    async fn nickname_history<'a>(&self, ctx: &Context<'a>) -> Result<Vec<NicknameChange>> {
+        let changes = ctx
+            .data_unchecked::<AssetRepository>()
+            .nickname_history(&self.snapshot.identity_id)?;
+
+        Ok(changes.into_iter().map(NicknameChange).collect())
+    }
+
+    /// The changes made to the asset through the API, oldest first.
+    async fn audit_trail<'a>(&self, ctx: &Context<'a>) -> Result<Vec<AuditEntry>> {
+        let entries = ctx
+            .data_unchecked::<AssetRepository>()
+            .audit_trail(&self.snapshot.identity_id)?;
+
+        Ok(entries.into_iter().map(AuditEntry).collect())
+    }
+
+    /// Every snapshot of the asset with a balance date in a range, newest first.
+    #[graphql(complexity = "connection_complexity(first, None, child_complexity)")]
+    async fn history<'a>(
+        &self,
+        ctx: &Context<'a>,
+        after: Option<String>,
+        first: Option<i32>,
+        #[graphql(desc = "the earliest balance date to include")] from: Option<
+            chrono::DateTime<chrono::Utc>,
+        >,
+        #[graphql(desc = "the latest balance date to include")] to: Option<
+            chrono::DateTime<chrono::Utc>,
+        >,
+    ) -> Result<Connection<SnapshotCursor, BalanceSnapshot>> {
+        query_snapshots(
+            ctx.data_unchecked::<AssetRepository>(),
+            ctx.data_unchecked::<Limits>(),
+            &self.snapshot.identity_id,
+            self.known_at,
+            from,
+            to,
+            after,
+            first,
+        )
+            .await
+    }
+}
+
+pub struct BalanceSnapshot(Asset);
+
+/// The balance of an asset recorded for a single date.
+#[Object]
+impl BalanceSnapshot {
+    /// The global id of the snapshot, which refetches its latest correction.
+    async fn id(&self) -> ID {
+        GlobalId::BalanceSnapshot {
+            asset_id: self.0.snapshot.asset_id.clone(),
+            balance_as_of: self.0.snapshot.balance_as_of,
+        }
+            .into()
+    }
+
+    /// The id of the asset in the source system the snapshot was imported for.
+    async fn asset_id(&self) -> &str {
+        &self.0.snapshot.asset_id
+    }
+
+    /// The nickname of the asset at the time of the snapshot.
+    async fn nickname(&self) -> &str {
+        &self.0.snapshot.nickname
+    }
+
+    /// The balance of the asset.
+    async fn balance(&self) -> f64 {
+        self.0.snapshot.balance_current
+    }
+
+    /// The datetime of the balance.
+    async fn balance_as_of(&self) -> &DateTime<Utc> {
+        &self.0.snapshot.balance_as_of
+    }
+
+    /// The cost basis of the balance, when known.
+    async fn cost_basis(&self) -> Option<f64> {
+        self.0.snapshot.balance_cost_basis
+    }
+
+    /// Every attribute of the asset as recorded in the snapshot.
+    async fn asset(&self) -> &Asset {
+        &self.0
+    }
+}
+
+/// An object that can be refetched by its global id.
+#[derive(Interface)]
+#[graphql(field(name = "id", ty = "ID", desc = "The opaque global id of the object."))]
+pub enum Node {
+    Asset(Asset),
+    BalanceSnapshot(BalanceSnapshot),
+}
+
+/// The type and key of an object, encoded into an opaque global id.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum GlobalId {
+    Asset {
+        identity_id: String,
+    },
+    BalanceSnapshot {
+        asset_id: String,
+        balance_as_of: DateTime<Utc>,
+    },
+}
+
+impl From<GlobalId> for ID {
+    fn from(id: GlobalId) -> Self {
+        ID(encode_opaque(&id))
+    }
+}
+
+impl TryFrom<&ID> for GlobalId {
+    type Error = Error;
+
+    fn try_from(id: &ID) -> Result<Self> {
+        decode_opaque(id).map_err(|_| Error::new("Invalid global id"))
+    }
+}
+
+pub struct NicknameChange(db::NicknameChange);
+
+/// A nickname an asset was known by.
+#[Object]
+impl NicknameChange {
+    /// The nickname of the asset.
+    async fn nickname(&self) -> &str {
+        &self.0.nickname
+    }
+
+    /// The balance date the nickname was first used.
+    async fn since(&self) -> &DateTime<Utc> {
+        &self.0.since
+    }
+}
+
+pub struct AuditEntry(db::AuditEntry);
+
+/// A change made to an asset through the API.
+#[Object]
+impl AuditEntry {
+    /// The system time the change was recorded at.
+    async fn recorded_at(&self) -> &DateTime<Utc> {
+        &self.0.recorded_at
+    }
+
+    /// The mutation that made the change.
+    async fn action(&self) -> &str {
+        &self.0.action
+    }
+
+    /// The asset id of the snapshot the change produced.
+    async fn asset_id(&self) -> &str {
+        &self.0.asset_id
+    }
+
+    /// The balance date of the snapshot the change produced.
     async fn balance_as_of(&self) -> &DateTime<Utc> {
         &self.0.balance_as_of
     }
+
+    /// The changed attributes.
+    async fn details(&self) -> Result<Json<serde_json::Value>> {
+        Ok(Json(serde_json::from_str(&self.0.details)?))
+    }
+}
+
+/// The primary category of an asset.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum PrimaryAssetCategory {
+    Cash,
+    Investment,
+    RealEstate,
+    OtherProperty,
+    /// A category that is not known to this API. The raw value is exposed alongside it.
+    Unknown,
+}
+
+impl From<&db::PrimaryAssetCategory> for PrimaryAssetCategory {
+    fn from(category: &db::PrimaryAssetCategory) -> Self {
+        match category {
+            db::PrimaryAssetCategory::Cash => PrimaryAssetCategory::Cash,
+            db::PrimaryAssetCategory::Investment => PrimaryAssetCategory::Investment,
+            db::PrimaryAssetCategory::RealEstate => PrimaryAssetCategory::RealEstate,
+            db::PrimaryAssetCategory::OtherProperty => PrimaryAssetCategory::OtherProperty,
+            db::PrimaryAssetCategory::Unknown(_) => PrimaryAssetCategory::Unknown,
+        }
+    }
+}
+
+impl From<PrimaryAssetCategory> for db::PrimaryAssetCategory {
+    fn from(category: PrimaryAssetCategory) -> Self {
+        match category {
+            PrimaryAssetCategory::Cash => db::PrimaryAssetCategory::Cash,
+            PrimaryAssetCategory::Investment => db::PrimaryAssetCategory::Investment,
+            PrimaryAssetCategory::RealEstate => db::PrimaryAssetCategory::RealEstate,
+            PrimaryAssetCategory::OtherProperty => db::PrimaryAssetCategory::OtherProperty,
+            PrimaryAssetCategory::Unknown => db::PrimaryAssetCategory::Unknown(String::new()),
+        }
+    }
+}
+
+/// The type of wealth an asset represents.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum WealthAssetType {
+    Brokerage,
+    Cash,
+    Cryptocurrency,
+    RealEstate,
+    Vehicle,
+    /// A type that is not known to this API. The raw value is exposed alongside it.
+    Unknown,
+}
+
+impl From<&db::WealthAssetType> for WealthAssetType {
+    fn from(asset_type: &db::WealthAssetType) -> Self {
+        match asset_type {
+            db::WealthAssetType::Brokerage => WealthAssetType::Brokerage,
+            db::WealthAssetType::Cash => WealthAssetType::Cash,
+            db::WealthAssetType::Cryptocurrency => WealthAssetType::Cryptocurrency,
+            db::WealthAssetType::RealEstate => WealthAssetType::RealEstate,
+            db::WealthAssetType::Vehicle => WealthAssetType::Vehicle,
+            db::WealthAssetType::Unknown(_) => WealthAssetType::Unknown,
+        }
+    }
+}
+
+impl From<WealthAssetType> for db::WealthAssetType {
+    fn from(asset_type: WealthAssetType) -> Self {
+        match asset_type {
+            WealthAssetType::Brokerage => db::WealthAssetType::Brokerage,
+            WealthAssetType::Cash => db::WealthAssetType::Cash,
+            WealthAssetType::Cryptocurrency => db::WealthAssetType::Cryptocurrency,
+            WealthAssetType::RealEstate => db::WealthAssetType::RealEstate,
+            WealthAssetType::Vehicle => db::WealthAssetType::Vehicle,
+            WealthAssetType::Unknown => db::WealthAssetType::Unknown(String::new()),
+        }
+    }
+}
+
+/// Restricts balances to assets whose latest snapshot matches every given condition.
+#[derive(InputObject, Default)]
+pub struct BalanceFilter {
+    /// Only assets in one of these categories. `UNKNOWN` matches every category not known to this API.
+    categories: Option<Vec<PrimaryAssetCategory>>,
+    /// Only assets of one of these types. `UNKNOWN` matches every type not known to this API.
+    wealth_asset_types: Option<Vec<WealthAssetType>>,
+    is_active: Option<bool>,
+    is_favorite: Option<bool>,
+    include_in_net_worth: Option<bool>,
+    is_asset: Option<bool>,
+    /// Only assets with at least this balance.
+    min_balance: Option<f64>,
+    /// Only assets with at most this balance.
+    max_balance: Option<f64>,
+    /// Only assets whose nickname contains this text, ignoring case.
+    nickname_contains: Option<String>,
+}
+
+impl From<BalanceFilter> for db::BalanceFilter {
+    fn from(filter: BalanceFilter) -> Self {
+        Self {
+            categories: filter
+                .categories
+                .map(|categories| categories.into_iter().map(Into::into).collect()),
+            wealth_asset_types: filter
+                .wealth_asset_types
+                .map(|types| types.into_iter().map(Into::into).collect()),
+            is_active: filter.is_active,
+            is_favorite: filter.is_favorite,
+            include_in_net_worth: filter.include_in_net_worth,
+            is_asset: filter.is_asset,
+            min_balance: filter.min_balance,
+            max_balance: filter.max_balance,
+            nickname: filter.nickname_contains,
+        }
+    }
+}
+
+/// A column balances can be sorted by.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum BalanceSortField {
+    Nickname,
+    Balance,
+    BalanceAsOf,
+    Category,
+    /// Favorites before other assets in ascending order, after them in descending order.
+    FavoriteFirst,
+}
+
+impl From<BalanceSortField> for db::BalanceSortField {
+    fn from(field: BalanceSortField) -> Self {
+        match field {
+            BalanceSortField::Nickname => db::BalanceSortField::Nickname,
+            BalanceSortField::Balance => db::BalanceSortField::Balance,
+            BalanceSortField::BalanceAsOf => db::BalanceSortField::BalanceAsOf,
+            BalanceSortField::Category => db::BalanceSortField::Category,
+            BalanceSortField::FavoriteFirst => db::BalanceSortField::FavoriteFirst,
+        }
+    }
+}
+
+/// The direction of a sort.
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Default)]
+pub enum SortDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// One level of the sort order of balances.
+#[derive(InputObject)]
+pub struct BalanceOrder {
+    field: BalanceSortField,
+    #[graphql(default)]
+    direction: SortDirection,
+}
+
+impl From<BalanceOrder> for db::BalanceOrder {
+    fn from(order: BalanceOrder) -> Self {
+        Self {
+            field: order.field.into(),
+            descending: order.direction == SortDirection::Desc,
+        }
+    }
+}
+
+pub struct NetWorth {
+    as_of: DateTime<Utc>,
+    totals: db::NetWorthTotals,
+    by_category: Vec<CategoryNetWorth>,
+    by_type: Vec<TypeNetWorth>,
+}
+
+impl NetWorth {
+    fn new(as_of: DateTime<Utc>, net_worth: db::NetWorth) -> Self {
+        Self {
+            as_of,
+            totals: net_worth.totals,
+            by_category: net_worth
+                .by_category
+                .into_iter()
+                .map(|(category, totals)| CategoryNetWorth { category, totals })
+                .collect(),
+            by_type: net_worth
+                .by_type
+                .into_iter()
+                .map(|(asset_type, totals)| TypeNetWorth { asset_type, totals })
+                .collect(),
+        }
+    }
+}
+
+/// The net worth of all assets that are included in net worth.
+#[Object]
+impl NetWorth {
+    /// The date the net worth was calculated as of.
+    async fn as_of(&self) -> &DateTime<Utc> {
+        &self.as_of
+    }
+
+    /// Assets minus liabilities.
+    async fn net_worth(&self) -> f64 {
+        self.totals.net_worth()
+    }
+
+    /// The sum of all asset balances.
+    async fn assets(&self) -> f64 {
+        self.totals.assets
+    }
+
+    /// The sum of all liability balances.
+    async fn liabilities(&self) -> f64 {
+        self.totals.liabilities
+    }
+
+    /// The net worth broken down by primary asset category.
+    async fn by_category(&self) -> &[CategoryNetWorth] {
+        &self.by_category
+    }
+
+    /// The net worth broken down by wealth asset type.
+    async fn by_type(&self) -> &[TypeNetWorth] {
+        &self.by_type
+    }
+}
+
+pub struct CategoryNetWorth {
+    category: db::PrimaryAssetCategory,
+    totals: db::NetWorthTotals,
+}
+
+/// The net worth of the assets in a single primary asset category.
+#[Object]
+impl CategoryNetWorth {
+    /// The primary asset category.
+    async fn category(&self) -> PrimaryAssetCategory {
+        PrimaryAssetCategory::from(&self.category)
+    }
+
+    /// The primary asset category as it was imported.
+    async fn raw_category(&self) -> &str {
+        self.category.name()
+    }
+
+    /// Assets minus liabilities in the category.
+    async fn net_worth(&self) -> f64 {
+        self.totals.net_worth()
+    }
+
+    /// The sum of asset balances in the category.
+    async fn assets(&self) -> f64 {
+        self.totals.assets
+    }
+
+    /// The sum of liability balances in the category.
+    async fn liabilities(&self) -> f64 {
+        self.totals.liabilities
+    }
+}
+
+pub struct TypeNetWorth {
+    asset_type: db::WealthAssetType,
+    totals: db::NetWorthTotals,
+}
+
+/// The net worth of the assets of a single wealth asset type.
+#[Object]
+impl TypeNetWorth {
+    /// The wealth asset type.
+    async fn asset_type(&self) -> WealthAssetType {
+        WealthAssetType::from(&self.asset_type)
+    }
+
+    /// The wealth asset type as it was imported.
+    async fn raw_asset_type(&self) -> &str {
+        self.asset_type.name()
+    }
+
+    /// Assets minus liabilities of the type.
+    async fn net_worth(&self) -> f64 {
+        self.totals.net_worth()
+    }
+
+    /// The sum of asset balances of the type.
+    async fn assets(&self) -> f64 {
+        self.totals.assets
+    }
+
+    /// The sum of liability balances of the type.
+    async fn liabilities(&self) -> f64 {
+        self.totals.liabilities
+    }
+}
+
+pub struct Performance {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    performance: db::Performance,
+}
+
+/// The change in net worth and in the balance of every asset between two dates.
+#[Object]
+impl Performance {
+    /// The starting date.
+    async fn from(&self) -> &DateTime<Utc> {
+        &self.from
+    }
+
+    /// The ending date.
+    async fn to(&self) -> &DateTime<Utc> {
+        &self.to
+    }
+
+    /// The net worth at the starting date.
+    async fn starting_net_worth(&self) -> f64 {
+        self.performance.starting.net_worth()
+    }
+
+    /// The net worth at the ending date.
+    async fn ending_net_worth(&self) -> f64 {
+        self.performance.ending.net_worth()
+    }
+
+    /// The absolute change in net worth.
+    async fn change(&self) -> f64 {
+        self.performance.change()
+    }
+
+    /// The change in net worth in percent. Null when the starting net worth is zero.
+    async fn change_percent(&self) -> Option<f64> {
+        self.performance.change_percent()
+    }
+
+    /// The change in balance of every asset that has a snapshot by the ending date.
+    async fn assets(&self) -> Vec<AssetPerformance<'_>> {
+        self.performance.assets.iter().map(AssetPerformance).collect()
+    }
+}
+
+pub struct AssetPerformance<'a>(&'a db::AssetPerformance);
+
+/// The change in balance of a single asset between two dates.
+#[Object]
+impl AssetPerformance<'_> {
+    /// The stable identity of the asset.
+    async fn identity_id(&self) -> &str {
+        &self.0.identity_id
+    }
+
+    /// The nickname of the asset at the ending date.
+    async fn nickname(&self) -> &str {
+        &self.0.nickname
+    }
+
+    /// The balance at the starting date. Null when the asset had no snapshot yet.
+    async fn starting_balance(&self) -> Option<f64> {
+        self.0.starting_balance
+    }
+
+    /// The balance at the ending date.
+    async fn ending_balance(&self) -> f64 {
+        self.0.ending_balance
+    }
+
+    /// The absolute change in balance.
+    async fn change(&self) -> f64 {
+        self.0.change()
+    }
+
+    /// The change in balance in percent. Null when there is no non-zero starting balance.
+    async fn change_percent(&self) -> Option<f64> {
+        self.0.change_percent()
+    }
+
+    /// The cost basis at the ending date, when known.
+    async fn cost_basis(&self) -> Option<f64> {
+        self.0.balance_cost_basis
+    }
+
+    /// The ending balance minus the cost basis.
+    async fn unrealized_gain(&self) -> Option<f64> {
+        self.0.unrealized_gain()
+    }
+
+    /// The unrealized gain in percent of the cost basis. Null when the cost basis is unknown or zero.
+    async fn unrealized_gain_percent(&self) -> Option<f64> {
+        self.0.unrealized_gain_percent()
+    }
+}
+
+/// The width of the buckets in a time series.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum Interval {
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl From<Interval> for db::Interval {
+    fn from(interval: Interval) -> Self {
+        match interval {
+            Interval::Day => db::Interval::Day,
+            Interval::Week => db::Interval::Week,
+            Interval::Month => db::Interval::Month,
+            Interval::Quarter => db::Interval::Quarter,
+            Interval::Year => db::Interval::Year,
+        }
+    }
+}
+
+pub struct BalancePoint(db::BalanceBucket);
+
+/// The balance of an asset at the close of a time series bucket.
+#[Object]
+impl BalancePoint {
+    /// The start of the bucket.
+    async fn starts_at(&self) -> &DateTime<Utc> {
+        &self.0.starts_at
+    }
+
+    /// The moment the balance was taken at, the end of the bucket or of the requested range.
+    async fn closes_at(&self) -> &DateTime<Utc> {
+        &self.0.closes_at
+    }
+
+    /// The last known balance, carried forward from earlier buckets. Null before the first snapshot.
+    async fn balance(&self) -> Option<f64> {
+        self.0.balance
+    }
+
+    /// The datetime of the snapshot the balance was taken from.
+    async fn balance_as_of(&self) -> Option<&DateTime<Utc>> {
+        self.0.balance_as_of.as_ref()
+    }
+}
+
+pub struct NetWorthPoint(db::NetWorthBucket);
+
+/// The net worth at the close of a time series bucket.
+#[Object]
+impl NetWorthPoint {
+    /// The start of the bucket.
+    async fn starts_at(&self) -> &DateTime<Utc> {
+        &self.0.starts_at
+    }
+
+    /// The moment the net worth was taken at, the end of the bucket or of the requested range.
+    async fn closes_at(&self) -> &DateTime<Utc> {
+        &self.0.closes_at
+    }
+
+    /// Assets minus liabilities.
+    async fn net_worth(&self) -> f64 {
+        self.0.totals.net_worth()
+    }
+
+    /// The sum of all asset balances.
+    async fn assets(&self) -> f64 {
+        self.0.totals.assets
+    }
+
+    /// The sum of all liability balances.
+    async fn liabilities(&self) -> f64 {
+        self.0.totals.liabilities
+    }
 }
 
 pub struct QueryRoot;
 
-#[Object]
+#[Object(guard = "RequireScope(Scope::AssetsRead).and(ServedBy(Role::Query))")]
 impl QueryRoot {
     /// The balances of assets as of a given date.
+    #[allow(clippy::too_many_arguments)]
+    #[graphql(complexity = "connection_complexity(first, last, child_complexity)")]
     async fn balance_as_of<'a>(
         &self,
         ctx: &Context<'a>,
         #[graphql(desc = "the cutoff date for balance updates, defaults to now")] as_of: Option<
             chrono::DateTime<chrono::Utc>,
         >,
+        #[graphql(desc = "the system time to answer as of, reproducing what was known at that moment; defaults to now")]
+        known_at: Option<chrono::DateTime<chrono::Utc>>,
+        #[graphql(desc = "only balances of assets matching the filter")] filter: Option<
+            BalanceFilter,
+        >,
+        #[graphql(desc = "the sort order, applied in sequence; ties are broken by nickname")]
+        order_by: Option<Vec<BalanceOrder>>,
         after: Option<String>,
         before: Option<String>,
         first: Option<i32>,
         last: Option<i32>,
-    ) -> Result<Connection<usize, Asset>> {
+    ) -> Result<Connection<BalanceCursor, Asset, BalanceConnectionFields>> {
         query_assets(
             ctx.data_unchecked::<AssetRepository>(),
+            ctx.data_unchecked::<Limits>(),
             as_of,
+            known_at,
+            filter.unwrap_or_default().into(),
+            order_by
+                .unwrap_or_default()
+                .into_iter()
+                .map(Into::into)
+                .collect(),
             after,
             before,
             first,
             last,
-            Asset,
+            Asset::new,
         )
             .await
     }
+
+    /// A single asset by any asset id grouped under its identity, or by its wid.
+    async fn asset<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "an asset id or wid")] id: String,
+        #[graphql(desc = "the cutoff date for balance updates, defaults to now")] as_of: Option<
+            chrono::DateTime<chrono::Utc>,
+        >,
+        #[graphql(desc = "the system time to answer as of, reproducing what was known at that moment; defaults to now")]
+        known_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<Option<Asset>> {
+        let now = chrono::Utc::now();
+        let known_at = known_at.unwrap_or(now);
+        let asset = find_asset(ctx.data_unchecked::<AssetRepository>(), &id, as_of.unwrap_or(now), known_at)?;
+
+        Ok(asset.map(|asset| Asset::new(asset, known_at)))
+    }
+
+    /// Refetches an object by its global id, as it is known now.
+    async fn node<'a>(&self, ctx: &Context<'a>, id: ID) -> Result<Option<Node>> {
+        let asset_repository = ctx.data_unchecked::<AssetRepository>();
+        let now = chrono::Utc::now();
+
+        let node = match GlobalId::try_from(&id)? {
+            GlobalId::Asset { identity_id } => asset_repository
+                .find_asset(&identity_id, now, now)?
+                .filter(|asset| asset.identity_id == identity_id)
+                .map(|asset| Node::Asset(Asset::new(asset, now))),
+            GlobalId::BalanceSnapshot {
+                asset_id,
+                balance_as_of,
+            } => asset_repository
+                .find_snapshot(&asset_id, balance_as_of, now)?
+                .map(|asset| Node::BalanceSnapshot(BalanceSnapshot(Asset::new(asset, now)))),
+        };
+
+        Ok(node)
+    }
+
+    /// The net worth of assets as of a given date.
+    async fn net_worth<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "the cutoff date for balance updates, defaults to now")] as_of: Option<
+            chrono::DateTime<chrono::Utc>,
+        >,
+        #[graphql(desc = "the system time to answer as of, reproducing what was known at that moment; defaults to now")]
+        known_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<NetWorth> {
+        let now = chrono::Utc::now();
+        let as_of = as_of.unwrap_or(now);
+        let net_worth = ctx
+            .data_unchecked::<AssetRepository>()
+            .net_worth(as_of, known_at.unwrap_or(now))?;
+
+        Ok(NetWorth::new(as_of, net_worth))
+    }
+
+    /// The change in net worth and in the balance of every asset between two dates.
+    async fn performance<'a>(
+        &self,
+        ctx: &Context<'a>,
+        from: chrono::DateTime<chrono::Utc>,
+        #[graphql(desc = "the ending date, defaults to now")] to: Option<
+            chrono::DateTime<chrono::Utc>,
+        >,
+        #[graphql(desc = "the system time to answer as of, reproducing what was known at that moment; defaults to now")]
+        known_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<Performance> {
+        let now = chrono::Utc::now();
+        let to = to.unwrap_or(now);
+        if from > to {
+            return Err(Error::new("`from` must not be after `to`"));
+        }
+
+        let performance = ctx
+            .data_unchecked::<AssetRepository>()
+            .performance(from, to, known_at.unwrap_or(now))?;
+
+        Ok(Performance {
+            from,
+            to,
+            performance,
+        })
+    }
+
+    /// The balance of an asset at the close of every interval in a date range.
+    async fn balance_history<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "any asset id grouped under the asset's identity")] asset_id: String,
+        #[graphql(desc = "the start of the range, truncated to the start of its interval")]
+        from: chrono::DateTime<chrono::Utc>,
+        #[graphql(desc = "the end of the range, defaults to now")] to: Option<
+            chrono::DateTime<chrono::Utc>,
+        >,
+        interval: Interval,
+        #[graphql(desc = "the system time to answer as of, reproducing what was known at that moment; defaults to now")]
+        known_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<Vec<BalancePoint>> {
+        let now = chrono::Utc::now();
+        let to = to.unwrap_or(now);
+        check_history_range(from, to, interval.into(), ctx.data_unchecked::<Limits>())?;
+
+        let buckets = ctx
+            .data_unchecked::<AssetRepository>()
+            .balance_history(&asset_id, from, to, interval.into(), known_at.unwrap_or(now))?
+            .ok_or_else(|| Error::new(format!("Asset {asset_id} does not exist")))?;
+
+        Ok(buckets.into_iter().map(BalancePoint).collect())
+    }
+
+    /// The net worth at the close of every interval in a date range.
+    async fn net_worth_history<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "the start of the range, truncated to the start of its interval")]
+        from: chrono::DateTime<chrono::Utc>,
+        #[graphql(desc = "the end of the range, defaults to now")] to: Option<
+            chrono::DateTime<chrono::Utc>,
+        >,
+        interval: Interval,
+        #[graphql(desc = "the system time to answer as of, reproducing what was known at that moment; defaults to now")]
+        known_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<Vec<NetWorthPoint>> {
+        let now = chrono::Utc::now();
+        let to = to.unwrap_or(now);
+        check_history_range(from, to, interval.into(), ctx.data_unchecked::<Limits>())?;
+
+        let buckets = ctx.data_unchecked::<AssetRepository>().net_worth_history(
+            from,
+            to,
+            interval.into(),
+            known_at.unwrap_or(now),
+        )?;
+
+        Ok(buckets.into_iter().map(NetWorthPoint).collect())
+    }
+}
+
+/// A snapshot of an asset to import, with the attributes of the import API that are stored.
+#[derive(InputObject)]
+pub struct AssetInput {
+    asset_id: String,
+    wid: uuid::Uuid,
+    nickname: String,
+    balance_current: f64,
+    balance_as_of: DateTime<Utc>,
+    balance_cost_basis: Option<f64>,
+    primary_asset_category: PrimaryAssetCategory,
+    /// The imported name of the category. Required when the category is `UNKNOWN`.
+    raw_primary_asset_category: Option<String>,
+    wealth_asset_type: WealthAssetType,
+    /// The imported name of the type. Required when the type is `UNKNOWN`.
+    raw_wealth_asset_type: Option<String>,
+    include_in_net_worth: bool,
+    is_active: bool,
+    is_asset: bool,
+    is_favorite: bool,
+    deactivate_by: Option<DateTime<Utc>>,
+    creation_date: DateTime<Utc>,
+    modification_date: DateTime<Utc>,
+    last_update: DateTime<Utc>,
+    last_update_attempt: DateTime<Utc>,
+}
+
+impl TryFrom<AssetInput> for db::Asset {
+    type Error = anyhow::Error;
+
+    fn try_from(input: AssetInput) -> std::result::Result<Self, Self::Error> {
+        let mut errors = Vec::new();
+
+        let primary_asset_category = match (input.primary_asset_category, input.raw_primary_asset_category) {
+            (PrimaryAssetCategory::Unknown, Some(raw)) if !raw.trim().is_empty() => {
+                db::PrimaryAssetCategory::Unknown(raw)
+            }
+            (PrimaryAssetCategory::Unknown, _) => {
+                errors.push(import::FieldError::new(
+                    "rawPrimaryAssetCategory",
+                    "is required when primaryAssetCategory is UNKNOWN",
+                ));
+                db::PrimaryAssetCategory::Unknown(String::new())
+            }
+            (category, _) => category.into(),
+        };
+        let wealth_asset_type = match (input.wealth_asset_type, input.raw_wealth_asset_type) {
+            (WealthAssetType::Unknown, Some(raw)) if !raw.trim().is_empty() => {
+                db::WealthAssetType::Unknown(raw)
+            }
+            (WealthAssetType::Unknown, _) => {
+                errors.push(import::FieldError::new(
+                    "rawWealthAssetType",
+                    "is required when wealthAssetType is UNKNOWN",
+                ));
+                db::WealthAssetType::Unknown(String::new())
+            }
+            (asset_type, _) => asset_type.into(),
+        };
+
+        let asset: db::Asset = import::Snapshot {
+            asset_id: input.asset_id,
+            balance_as_of: input.balance_as_of,
+            balance_cost_basis: input.balance_cost_basis,
+            balance_current: input.balance_current,
+            creation_date: input.creation_date,
+            deactivate_by: input.deactivate_by,
+            include_in_net_worth: input.include_in_net_worth,
+            is_active: input.is_active,
+            is_asset: input.is_asset,
+            is_favorite: input.is_favorite,
+            last_update: input.last_update,
+            last_update_attempt: input.last_update_attempt,
+            modification_date: input.modification_date,
+            nickname: input.nickname,
+            primary_asset_category,
+            wealth_asset_type,
+            wid: input.wid,
+            // Assets changed over GraphQL are recorded for the tenant of the request.
+            tenant_id: None,
+        }
+        .into();
+
+        if let Err(import::ValidationError(invalid)) = import::validate(&asset) {
+            errors.extend(invalid);
+        }
+
+        if errors.is_empty() {
+            Ok(asset)
+        } else {
+            Err(import::ValidationError(errors).into())
+        }
+    }
+}
+
+/// How `importAssets` treats invalid records.
+#[derive(InputObject, Default)]
+pub struct ImportOptions {
+    /// Import the valid records even when some are rejected. By default, nothing is imported unless every record is valid.
+    #[graphql(default)]
+    allow_partial: bool,
+}
+
+impl From<ImportOptions> for import::ImportPolicy {
+    fn from(options: ImportOptions) -> Self {
+        Self {
+            allow_partial: options.allow_partial,
+        }
+    }
+}
+
+/// The outcome of an import for every record.
+pub struct ImportResult(import::ImportReport);
+
+#[Object]
+impl ImportResult {
+    /// Records of snapshots that were not known before.
+    async fn inserted(&self) -> Vec<ImportedRecord<'_>> {
+        self.0.inserted.iter().map(ImportedRecord).collect()
+    }
+
+    /// Records that corrected a known snapshot.
+    async fn updated(&self) -> Vec<ImportedRecord<'_>> {
+        self.0.updated.iter().map(ImportedRecord).collect()
+    }
+
+    /// Records that were invalid and therefore not imported.
+    async fn rejected(&self) -> Vec<RejectedRecord<'_>> {
+        self.0.rejected.iter().map(RejectedRecord).collect()
+    }
+}
+
+pub struct ImportedRecord<'a>(&'a import::ImportedRecord);
+
+/// A record that was imported.
+#[Object]
+impl ImportedRecord<'_> {
+    /// The position of the record in the input.
+    async fn index(&self) -> usize {
+        self.0.index
+    }
+
+    async fn asset_id(&self) -> &str {
+        &self.0.asset_id
+    }
+
+    async fn balance_as_of(&self) -> &DateTime<Utc> {
+        &self.0.balance_as_of
+    }
+}
+
+pub struct RejectedRecord<'a>(&'a import::RejectedRecord);
+
+/// A record that was rejected.
+#[Object]
+impl RejectedRecord<'_> {
+    /// The position of the record in the input.
+    async fn index(&self) -> usize {
+        self.0.index
+    }
+
+    /// The problems with the fields of the record.
+    async fn errors(&self) -> Vec<FieldError<'_>> {
+        self.0.errors.iter().map(FieldError).collect()
+    }
+}
+
+pub struct FieldError<'a>(&'a import::FieldError);
+
+/// A problem with a single field of a record.
+#[Object]
+impl FieldError<'_> {
+    /// The name of the field in the input.
+    async fn field(&self) -> &str {
+        &self.0.field
+    }
+
+    async fn message(&self) -> &str {
+        &self.0.message
+    }
+}
+
+pub struct MutationRoot;
+
+/// Changes to assets, recorded in the audit trail. Attribute changes correct the latest snapshot of an asset rather
+/// than adding a balance, so its balance history is unchanged, while `recordBalance` records a balance for a date.
+/// Earlier answers stay reproducible through `knownAt`.
+#[Object(guard = "RequireScope(Scope::ImportWrite).and(ServedBy(Role::Import))")]
+impl MutationRoot {
+    /// Imports asset snapshots through the same insert pipeline as the import API, validating them like other changes.
+    async fn import_assets<'a>(
+        &self,
+        ctx: &Context<'a>,
+        input: Vec<AssetInput>,
+        options: Option<ImportOptions>,
+    ) -> Result<ImportResult> {
+        let quota = ctx.data_opt::<ImportQuota>();
+        let reservation = quota
+            .map(|quota| quota.reserve(input.len()))
+            .transpose()
+            .map_err(|throttled| {
+                Error::new(&throttled.message).extend_with(|_, e| e.set("retryAfter", throttled.retry_after_secs()))
+            })?;
+
+        let result = import::import(
+            ctx.data_unchecked::<AssetRepository>(),
+            input,
+            options.unwrap_or_default().into(),
+        );
+        if let (Some(quota), Some(reservation)) = (quota, reservation) {
+            let recorded = result.as_ref().map_or(0, import::ImportReport::recorded);
+            quota.settle(reservation, recorded);
+        }
+
+        Ok(ImportResult(result?))
+    }
+
+    /// Renames an asset.
+    async fn rename_asset<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "any asset id grouped under the asset's identity")] id: String,
+        nickname: String,
+    ) -> Result<Asset> {
+        let details = serde_json::json!({ "nickname": nickname });
+        amend(ctx, &id, "renameAsset", details, None, |asset| {
+            asset.nickname = nickname;
+        })
+    }
+
+    /// Marks an asset as a favorite or not.
+    async fn set_favorite<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "any asset id grouped under the asset's identity")] id: String,
+        is_favorite: bool,
+    ) -> Result<Asset> {
+        let details = serde_json::json!({ "isFavorite": is_favorite });
+        amend(ctx, &id, "setFavorite", details, None, |asset| {
+            asset.is_favorite = is_favorite;
+        })
+    }
+
+    /// Includes an asset in net worth or excludes it.
+    async fn set_include_in_net_worth<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "any asset id grouped under the asset's identity")] id: String,
+        include_in_net_worth: bool,
+    ) -> Result<Asset> {
+        let details = serde_json::json!({ "includeInNetWorth": include_in_net_worth });
+        amend(ctx, &id, "setIncludeInNetWorth", details, None, |asset| {
+            asset.include_in_net_worth = include_in_net_worth;
+        })
+    }
+
+    /// Deactivates an asset.
+    async fn deactivate_asset<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "any asset id grouped under the asset's identity")] id: String,
+    ) -> Result<Asset> {
+        let details = serde_json::json!({ "isActive": false });
+        amend(ctx, &id, "deactivateAsset", details, None, |asset| {
+            asset.is_active = false;
+        })
+    }
+
+    /// Reactivates an asset, clearing any scheduled deactivation.
+    async fn reactivate_asset<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "any asset id grouped under the asset's identity")] id: String,
+    ) -> Result<Asset> {
+        let details = serde_json::json!({ "isActive": true, "deactivateBy": null });
+        amend(ctx, &id, "reactivateAsset", details, None, |asset| {
+            asset.is_active = true;
+            asset.deactivate_by = None;
+        })
+    }
+
+    /// Records a manual balance for an asset. Recording a balance for a date that already has one corrects it.
+    async fn record_balance<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "any asset id grouped under the asset's identity")] id: String,
+        balance: f64,
+        #[graphql(desc = "the date of the balance, defaults to now")] balance_as_of: Option<
+            chrono::DateTime<chrono::Utc>,
+        >,
+        #[graphql(desc = "the cost basis of the balance, defaults to the last known one")]
+        cost_basis: Option<f64>,
+    ) -> Result<Asset> {
+        let details = serde_json::json!({ "balance": balance, "costBasis": cost_basis });
+        amend(ctx, &id, "recordBalance", details, Some(balance_as_of.unwrap_or_else(Utc::now)), |asset| {
+            asset.balance_current = balance;
+            asset.balance_cost_basis = cost_basis.or(asset.balance_cost_basis);
+            asset.last_update = asset.modification_date;
+        })
+    }
+}
+
+/// Records a snapshot of an asset based on its latest snapshot as of the balance date, changed by `change`.
+///
+/// Without a balance date the latest snapshot is corrected instead, so changing attributes adds no balance point.
+fn amend<'a>(
+    ctx: &Context<'a>,
+    id: &str,
+    action: &str,
+    details: serde_json::Value,
+    balance_as_of: Option<DateTime<Utc>>,
+    change: impl FnOnce(&mut db::Asset),
+) -> Result<Asset> {
+    let asset_repository = ctx.data_unchecked::<AssetRepository>();
+    let now = Utc::now();
+    let as_of = balance_as_of.unwrap_or(now);
+
+    // Mutations only accept asset ids, since every account of a user shares their wid.
+    let Some(mut asset) = asset_repository.find_asset(id, as_of, now)? else {
+        return Err(Error::new(format!("Asset {id} does not exist as of {as_of}")));
+    };
+
+    // The latest snapshot may have been recorded for another asset grouped under the same identity.
+    asset.asset_id = id.to_string();
+    if let Some(balance_as_of) = balance_as_of {
+        asset.balance_as_of = balance_as_of;
+    }
+    asset.modification_date = now;
+    change(&mut asset);
+    import::validate(&asset)?;
+
+    let balance_as_of = asset.balance_as_of;
+    asset_repository.insert_audited(asset, action, &details.to_string())?;
+
+    let known_at = Utc::now();
+    asset_repository
+        .find_asset(id, balance_as_of, known_at)?
+        .map(|asset| Asset::new(asset, known_at))
+        .ok_or_else(|| Error::new(format!("Asset {id} does not exist")))
+}
+
+/// The wid an id refers to, when it is a UUID.
+fn parse_wid(id: &str) -> Option<i128> {
+    uuid::Uuid::parse_str(id).ok().map(|wid| wid.as_u128() as i128)
+}
+
+/// The asset an asset id is grouped under, or else the only asset recorded for a wid.
+///
+/// All accounts of a user share their wid, so a wid matching several assets is rejected rather than resolved to any of them.
+fn find_asset(
+    asset_repository: &AssetRepository,
+    id: &str,
+    as_of: DateTime<Utc>,
+    known_at: DateTime<Utc>,
+) -> Result<Option<db::Asset>> {
+    if let Some(asset) = asset_repository.find_asset(id, as_of, known_at)? {
+        return Ok(Some(asset));
+    }
+    let Some(wid) = parse_wid(id) else {
+        return Ok(None);
+    };
+
+    let mut assets = asset_repository.find_assets_by_wid(wid, as_of, known_at)?;
+    if assets.len() > 1 {
+        return Err(Error::new(format!(
+            "wid {id} matches {} assets, use an asset id instead",
+            assets.len()
+        )));
+    }
+
+    Ok(assets.pop())
+}
+
+pub struct SubscriptionRoot;
+
+/// Notifications of snapshots being recorded, published once they are committed.
+// Commits are only published within the server that made them, so subscriptions are served where imports are.
+#[Subscription(guard = "RequireScope(Scope::AssetsRead).and(ServedBy(Role::Import))")]
+impl SubscriptionRoot {
+    /// Every snapshot recorded from now on, by imports or changes, that matches the filter.
+    async fn balances_updated<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "only snapshots matching the filter")] filter: Option<BalanceFilter>,
+    ) -> impl Stream<Item = Asset> + use<> {
+        let filter: db::BalanceFilter = filter.unwrap_or_default().into();
+
+        committed(ctx).flat_map(move |committed| {
+            let assets: Vec<_> = committed
+                .snapshots
+                .iter()
+                .filter(|asset| filter.matches(asset))
+                .map(|asset| Asset::new(asset.clone(), committed.recorded_at))
+                .collect();
+            stream::iter(assets)
+        })
+    }
+
+    /// Every import completed from now on.
+    async fn import_completed<'a>(&self, ctx: &Context<'a>) -> impl Stream<Item = ImportCompleted> + use<> {
+        committed(ctx)
+            .filter(|committed| future::ready(committed.origin == db::Origin::Import))
+            .map(ImportCompleted)
+    }
+}
+
+/// The batches of snapshots committed from now on for the tenant of the request. Batches missed by a lagging subscriber are skipped.
+fn committed(ctx: &Context<'_>) -> impl Stream<Item = db::Committed> + use<> {
+    let asset_repository = ctx.data_unchecked::<AssetRepository>();
+    let tenant = asset_repository.tenant().map(str::to_string);
+    let receiver = asset_repository.subscribe();
+
+    stream::unfold((receiver, tenant), |(mut receiver, tenant)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(committed) => {
+                    if let Some(committed) = committed.visible_to(tenant.as_deref()) {
+                        return Some((committed, (receiver, tenant)));
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "Subscriber lagged behind committed snapshots");
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+pub struct ImportCompleted(db::Committed);
+
+/// An import that was committed.
+#[Object]
+impl ImportCompleted {
+    /// The system time the import was recorded at.
+    async fn recorded_at(&self) -> &DateTime<Utc> {
+        &self.0.recorded_at
+    }
+
+    /// The number of snapshots that were not known before.
+    async fn inserted(&self) -> usize {
+        self.0.inserted()
+    }
+
+    /// The number of snapshots that corrected a known snapshot.
+    async fn updated(&self) -> usize {
+        self.0.updated()
+    }
+}
+
+/// The version of the encoding of cursors and global ids, which changes whenever the shape of a payload does.
+const OPAQUE_VERSION: u8 = 1;
+
+/// An opaque cursor that pins the point in time a paginated query is answered as of,
+/// along with the position of an asset in the sort order.
+#[derive(Serialize, Deserialize)]
+pub struct BalanceCursor {
+    as_of: DateTime<Utc>,
+    known_at: DateTime<Utc>,
+    order: String,
+    key: db::BalanceKey,
+}
+
+impl CursorType for BalanceCursor {
+    type Error = InvalidCursor;
+
+    fn decode_cursor(s: &str) -> std::result::Result<Self, Self::Error> {
+        decode_opaque(s).map_err(|_| InvalidCursor)
+    }
+
+    fn encode_cursor(&self) -> String {
+        encode_opaque(self)
+    }
+}
+
+/// An opaque cursor that pins the system time a snapshot history is answered as of,
+/// along with the position of a snapshot in the history.
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotCursor {
+    known_at: DateTime<Utc>,
+    key: db::SnapshotKey,
+}
+
+impl CursorType for SnapshotCursor {
+    type Error = InvalidCursor;
+
+    fn decode_cursor(s: &str) -> std::result::Result<Self, Self::Error> {
+        decode_opaque(s).map_err(|_| InvalidCursor)
+    }
+
+    fn encode_cursor(&self) -> String {
+        encode_opaque(self)
+    }
 }
 
+/// A cursor that was not created by this service. Reported to clients as is, unlike internal errors.
+#[derive(Debug)]
+pub struct InvalidCursor;
+
+impl std::fmt::Display for InvalidCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid cursor")
+    }
+}
+
+/// Decodes a cursor or global id from URL-safe Base64 of the version byte followed by the JSON payload.
+fn decode_opaque<T: DeserializeOwned>(s: &str) -> anyhow::Result<T> {
+    let bytes = URL_SAFE_NO_PAD.decode(s)?;
+
+    match bytes.split_first() {
+        Some((&OPAQUE_VERSION, payload)) => Ok(serde_json::from_slice(payload)?),
+        _ => Err(anyhow!("Unsupported encoding version")),
+    }
+}
+
+/// Encodes a cursor or global id as URL-safe Base64 of the version byte followed by the JSON payload.
+fn encode_opaque<T: Serialize>(value: &T) -> String {
+    let mut bytes = vec![OPAQUE_VERSION];
+    serde_json::to_writer(&mut bytes, value).expect("opaque values are always serializable");
+
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Fields of a balance connection that describe all pages.
+#[derive(SimpleObject)]
+pub struct BalanceConnectionFields {
+    /// The number of assets matching the filter across all pages.
+    total_count: usize,
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn query_assets<F, T>(
     asset_repository: &AssetRepository,
+    limits: &Limits,
     as_of: Option<chrono::DateTime<chrono::Utc>>,
+    known_at: Option<chrono::DateTime<chrono::Utc>>,
+    filter: db::BalanceFilter,
+    order: Vec<db::BalanceOrder>,
     after: Option<String>,
     before: Option<String>,
     first: Option<i32>,
     last: Option<i32>,
     map_to: F,
-) -> Result<Connection<usize, T>>
+) -> Result<Connection<BalanceCursor, T, BalanceConnectionFields>>
 where
-    F: Fn(db::Asset) -> T,
+    F: Fn(db::Asset, DateTime<Utc>) -> T,
     T: OutputType,
 {
     query(
-        after.clone(),
-        before.clone(),
+        after,
+        before,
         first,
         last,
-        |after, before, first, last| async move {
-            let date_time = as_of.unwrap_or_else(chrono::Utc::now);
-            let total_count = asset_repository.count_balances(date_time)?;
+        |after: Option<BalanceCursor>, before: Option<BalanceCursor>, first, last| async move {
+            let size = page_size(first, last, limits)?;
+            let from_end = first.is_none() && last.is_some();
+            let now = chrono::Utc::now();
+            let fingerprint = db::order_fingerprint(&order);
+
+            // Cursors pin the point in time of the first page, so later pages are answered
+            // from the same snapshot even while new assets are imported.
+            let pinned = after.as_ref().or(before.as_ref());
+            if let (Some(after), Some(before)) = (&after, &before)
+                && (after.as_of != before.as_of || after.known_at != before.known_at)
+            {
+                return Err(Error::new("The cursors were created for different points in time"));
+            }
+            if pinned.is_some_and(|cursor| cursor.order != fingerprint) {
+                return Err(Error::new("The cursor was created for a different sort order"));
+            }
 
-            let (limit, offset) = calculate_limit_offset(total_count, after, before, first, last);
+            let date_time = pin(as_of, pinned.map(|c| c.as_of), now, "asOf")?;
+            let known_at = pin(known_at, pinned.map(|c| c.known_at), now, "knownAt")?;
+            let total_count = asset_repository.count_balances(date_time, known_at, &filter)?;
 
-            // Fetch assets
-            let assets = asset_repository.balances(date_time, limit, offset)?;
+            // Fetch one extra asset to find out whether there are more in the direction of travel.
+            let page = db::BalancePage {
+                after: after.map(|c| c.key),
+                before: before.map(|c| c.key),
+                limit: size + 1,
+                from_end,
+            };
+            let had_after = page.after.is_some();
+            let had_before = page.before.is_some();
+            let mut assets = asset_repository.balances(date_time, known_at, &filter, &order, &page)?;
 
-            let has_previous = offset > 0;
-            let has_next = offset + assets.len() < total_count;
+            let has_more = assets.len() > size;
+            if has_more && from_end {
+                assets.remove(0);
+            } else {
+                assets.truncate(size);
+            }
+            if let (Some(_), Some(last)) = (first, last)
+                && last < assets.len()
+            {
+                assets.drain(..assets.len() - last);
+            }
 
-            let mut connection = Connection::new(has_previous, has_next);
-            connection.edges.extend(
-                assets
-                    .into_iter()
-                    .enumerate()
-                    .map(|(idx, asset)| Edge::new(offset + idx, map_to(asset))),
+            let (has_previous, has_next) = if from_end {
+                (has_more, had_before)
+            } else {
+                (had_after, has_more)
+            };
+
+            let mut connection = Connection::with_additional_fields(
+                has_previous,
+                has_next,
+                BalanceConnectionFields { total_count },
             );
+            connection.edges.extend(assets.into_iter().map(|asset| {
+                let cursor = BalanceCursor {
+                    as_of: date_time,
+                    known_at,
+                    order: fingerprint.clone(),
+                    key: db::key_of(&order, &asset),
+                };
+                Edge::new(cursor, map_to(asset, known_at))
+            }));
+
+            Ok::<_, Error>(connection)
+        },
+    )
+        .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn query_snapshots(
+    asset_repository: &AssetRepository,
+    limits: &Limits,
+    identity_id: &str,
+    known_at: DateTime<Utc>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    after: Option<String>,
+    first: Option<i32>,
+) -> Result<Connection<SnapshotCursor, BalanceSnapshot>> {
+    if let (Some(from), Some(to)) = (from, to)
+        && from > to
+    {
+        return Err(Error::new("`from` must not be after `to`"));
+    }
+
+    query(
+        after,
+        None,
+        first,
+        None,
+        |after: Option<SnapshotCursor>, _: Option<SnapshotCursor>, first, _| async move {
+            // Later pages keep the system time of the first one, even though the asset is resolved anew.
+            let known_at = after.as_ref().map_or(known_at, |cursor| cursor.known_at);
+            let size = page_size(first, None, limits)?;
+
+            let mut snapshots = asset_repository.snapshots(
+                identity_id,
+                from,
+                to,
+                known_at,
+                after.as_ref().map(|cursor| &cursor.key),
+                size + 1,
+            )?;
+            let has_next = snapshots.len() > size;
+            snapshots.truncate(size);
+
+            let mut connection = Connection::new(after.is_some(), has_next);
+            connection.edges.extend(snapshots.into_iter().map(|snapshot| {
+                let cursor = SnapshotCursor {
+                    known_at,
+                    key: db::SnapshotKey::of(&snapshot),
+                };
+                Edge::new(cursor, BalanceSnapshot(Asset::new(snapshot, known_at)))
+            }));
 
             Ok::<_, Error>(connection)
         },
@@ -103,56 +1781,187 @@ where
         .await
 }
 
-fn calculate_limit_offset(total_count: usize, after: Option<usize>, before: Option<usize>, first: Option<usize>, last: Option<usize>) -> (usize, usize) {
-    // Default range
-    let mut start = 0usize;
-    let mut end = total_count;
+/// The point in time pinned by a cursor, which must match the requested point in time when both are given.
+fn pin(
+    requested: Option<DateTime<Utc>>,
+    pinned: Option<DateTime<Utc>>,
+    default: DateTime<Utc>,
+    argument: &str,
+) -> Result<DateTime<Utc>> {
+    match (requested, pinned) {
+        (Some(requested), Some(pinned)) if requested != pinned => Err(Error::new(format!(
+            "`{argument}` does not match the cursor"
+        ))),
+        (Some(requested), _) => Ok(requested),
+        (None, Some(pinned)) => Ok(pinned),
+        (None, None) => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn cursors_round_trip() {
+        let cursor = BalanceCursor {
+            as_of: Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap(),
+            known_at: Utc.with_ymd_and_hms(2025, 4, 1, 0, 0, 0).unwrap(),
+            order: db::order_fingerprint(&[]),
+            key: db::BalanceKey {
+                values: vec![db::SortValue::Text("Checking".to_string())],
+                identity_id: "a".to_string(),
+            },
+        };
+
+        let encoded = cursor.encode_cursor();
+        assert!(!encoded.contains("Checking"));
+        assert_eq!(16, cursor.order.len());
+        assert_ne!(cursor.order, db::order_fingerprint(&[db::BalanceOrder::ascending(db::BalanceSortField::Balance)]));
 
-    // Apply after/before
-    if let Some(a) = after {
-        start = a + 1;
+        let decoded = BalanceCursor::decode_cursor(&encoded).unwrap();
+        assert_eq!(cursor.as_of, decoded.as_of);
+        assert_eq!(cursor.known_at, decoded.known_at);
+        assert_eq!(cursor.order, decoded.order);
+        assert_eq!(cursor.key, decoded.key);
+
+        assert!(BalanceCursor::decode_cursor("100").is_err());
+        assert!(BalanceCursor::decode_cursor(&URL_SAFE_NO_PAD.encode([2, b'{', b'}'])).is_err());
+    }
+
+    #[test]
+    fn global_ids_round_trip() {
+        let id = GlobalId::BalanceSnapshot {
+            asset_id: "a".to_string(),
+            balance_as_of: Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap(),
+        };
+        let encoded = ID::from(id);
+
+        assert_eq!(
+            GlobalId::BalanceSnapshot {
+                asset_id: "a".to_string(),
+                balance_as_of: Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap(),
+            },
+            GlobalId::try_from(&encoded).unwrap()
+        );
+        assert!(GlobalId::try_from(&ID::from("a")).is_err());
     }
-    if let Some(b) = before {
-        end = b.saturating_sub(1);
-        start = end.saturating_sub(LIMIT);
+
+    #[test]
+    fn asset_inputs_report_field_errors() {
+        let now = Utc::now();
+        let input = AssetInput {
+            asset_id: "a".to_string(),
+            wid: uuid::Uuid::nil(),
+            nickname: " ".to_string(),
+            balance_current: 100.0,
+            balance_as_of: now,
+            balance_cost_basis: None,
+            primary_asset_category: PrimaryAssetCategory::Unknown,
+            raw_primary_asset_category: None,
+            wealth_asset_type: WealthAssetType::Cash,
+            raw_wealth_asset_type: None,
+            include_in_net_worth: true,
+            is_active: true,
+            is_asset: true,
+            is_favorite: false,
+            deactivate_by: None,
+            creation_date: now,
+            modification_date: now,
+            last_update: now,
+            last_update_attempt: now,
+        };
+
+        let error = db::Asset::try_from(input).err().unwrap();
+        let import::ValidationError(errors) = error.downcast().unwrap();
+        let fields: Vec<_> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(vec!["rawPrimaryAssetCategory", "nickname"], fields);
     }
-    if end > total_count {
-        end = total_count;
+
+    #[test]
+    fn history_ranges_above_the_maximum_are_rejected() {
+        let limits = Limits {
+            max_history_points: 12,
+            ..Limits::default()
+        };
+        let from = chrono::Utc.with_ymd_and_hms(2025, 1, 31, 0, 0, 0).unwrap();
+        let to = chrono::Utc.with_ymd_and_hms(2025, 12, 1, 0, 0, 0).unwrap();
+
+        assert!(check_history_range(from, to, db::Interval::Month, &limits).is_ok());
+        assert!(check_history_range(from, to, db::Interval::Day, &limits).is_err());
+        assert!(check_history_range(to, from, db::Interval::Year, &limits).is_err());
+        assert_eq!(2, db::Interval::Week.bucket_count(from, from + chrono::Days::new(3)));
+        assert_eq!(4, db::Interval::Quarter.bucket_count(from, to));
     }
-    if start > end {
-        start = end;
+
+    #[test]
+    fn page_sizes_above_the_maximum_are_rejected() {
+        let limits = Limits {
+            max_page_size: 10,
+            ..Limits::default()
+        };
+
+        assert_eq!(10, page_size(None, None, &limits).unwrap());
+        assert_eq!(5, page_size(Some(5), None, &limits).unwrap());
+        assert_eq!(10, page_size(None, Some(10), &limits).unwrap());
+        assert!(page_size(Some(11), None, &limits).is_err());
+        assert!(page_size(None, Some(11), &limits).is_err());
     }
 
-    // Apply first/last
-    if let Some(f) = first {
-        if f < end - start {
-            end = start + f;
-        }
+    #[test]
+    fn cursors_pin_points_in_time() {
+        let now = Utc::now();
+        let pinned = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+
+        assert_eq!(now, pin(None, None, now, "asOf").unwrap());
+        assert_eq!(pinned, pin(None, Some(pinned), now, "asOf").unwrap());
+        assert_eq!(pinned, pin(Some(pinned), Some(pinned), now, "asOf").unwrap());
+        assert!(pin(Some(now), Some(pinned), now, "asOf").is_err());
     }
-    if let Some(l) = last {
-        if l < end - start {
-            start = end - l;
+
+    fn snapshot(asset_id: &str, balance: f64, balance_as_of: DateTime<Utc>) -> db::Asset {
+        import::Snapshot {
+            asset_id: asset_id.to_string(),
+            balance_as_of,
+            balance_cost_basis: None,
+            balance_current: balance,
+            creation_date: balance_as_of,
+            deactivate_by: None,
+            include_in_net_worth: true,
+            is_active: true,
+            is_asset: true,
+            is_favorite: false,
+            last_update: balance_as_of,
+            last_update_attempt: balance_as_of,
+            modification_date: balance_as_of,
+            nickname: "Checking".to_string(),
+            primary_asset_category: db::PrimaryAssetCategory::Cash,
+            wealth_asset_type: db::WealthAssetType::Cash,
+            wid: uuid::Uuid::nil(),
+            tenant_id: None,
         }
+        .into()
     }
 
-    let limit = end.saturating_sub(start).min(LIMIT);
-    let offset = start;
+    #[tokio::test]
+    async fn attribute_changes_leave_the_balance_history_unchanged() {
+        let repository = AssetRepository::new(":memory:", 1, std::time::Duration::from_secs(1)).unwrap();
+        repository.setup().unwrap();
+        let first = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+        let second = Utc.with_ymd_and_hms(2025, 4, 28, 0, 0, 0).unwrap();
+        repository.insert(vec![snapshot("a", 1000.0, first), snapshot("b", 1500.0, second)]).unwrap();
+        assert!(repository.merge_identities("a", "b").unwrap());
 
-    (limit, offset)
-}
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let schema = build_schema(repository.clone(), Limits::default(), None, Mode::Development, Role::All);
+        let history = r#"{ balanceHistory(assetId: "a", from: "2025-03-01T00:00:00Z", interval: MONTH) { balance balanceAsOf } }"#;
+        let before = schema.execute(history).await.into_result().unwrap().data;
 
-    #[test]
-    fn limit_offset() {
-        let total = 1000;
-        assert_eq!((100, 0), calculate_limit_offset(total, None, None, None, None));
-        assert_eq!((100, 101), calculate_limit_offset(total, Some(100), None, None, None));
-        assert_eq!((100, 99), calculate_limit_offset(total, None, Some(200), None, None));
-        assert_eq!((50, 101), calculate_limit_offset(total, Some(100), None, Some(50), None));
-        assert_eq!((50, 149), calculate_limit_offset(total, None, Some(200), None, Some(50)));
-        assert_eq!((50, 0), calculate_limit_offset(total, None, None, Some(50), None));
-        assert_eq!((100, total-100), calculate_limit_offset(total, None, None, None, Some(100)));
+        let renamed = schema.execute(r#"mutation { renameAsset(id: "a", nickname: "Savings") { nickname } }"#).await;
+        assert!(renamed.errors.is_empty(), "{:?}", renamed.errors);
+
+        assert_eq!(before, schema.execute(history).await.into_result().unwrap().data);
+        let latest = repository.find_asset("a", Utc::now(), Utc::now()).unwrap().unwrap();
+        assert_eq!(("a", "Savings", second), (latest.asset_id.as_str(), latest.nickname.as_str(), latest.balance_as_of));
     }
 }
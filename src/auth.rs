@@ -0,0 +1,398 @@
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use async_graphql::{Context, Error, Guard, Result};
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::db::{AssetRepository, Scope};
+use crate::keys;
+
+/// Who a request was authenticated as and what it may do. Queries and imports of the request are scoped to its tenant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    /// The tenant whose data the request may see. Principals without a tenant see the data of every tenant.
+    pub tenant: Option<String>,
+    pub scopes: Vec<Scope>,
+    /// The API key the request was authenticated with, if any.
+    pub key_id: Option<String>,
+}
+
+impl Principal {
+    /// A user authenticated with a bearer token. Users are limited to their tenant, so they are never admins,
+    /// who could manage the keys of every tenant.
+    fn user(tenant: String, scopes: Vec<Scope>) -> Self {
+        Self {
+            tenant: Some(tenant),
+            scopes: scopes.into_iter().filter(|scope| *scope != Scope::Admin).collect(),
+            key_id: None,
+        }
+    }
+
+    /// Whether the principal was granted a scope. Admins are granted every scope.
+    pub fn has(&self, scope: Scope) -> bool {
+        self.scopes.contains(&Scope::Admin) || self.scopes.contains(&scope)
+    }
+}
+
+/// Rejects principals without a scope with `403 Forbidden`. Requests without a principal are let through,
+/// because they are only possible when authentication is disabled.
+pub fn authorize(principal: Option<&Principal>, scope: Scope) -> Result<(), Forbidden> {
+    match principal {
+        Some(principal) if !principal.has(scope) => Err(Forbidden(scope)),
+        _ => Ok(()),
+    }
+}
+
+/// Only resolves fields for principals with a scope, like `authorize` does for routes.
+pub struct RequireScope(pub Scope);
+
+impl Guard for RequireScope {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        match ctx.data_opt::<Principal>() {
+            Some(principal) if !principal.has(self.0) => {
+                Err(Error::new(format!("The {} scope is required", self.0)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The claims of a bearer token that identify the tenant and what it may do.
+#[derive(Deserialize)]
+struct Claims {
+    sub: Option<String>,
+    cognito_id: Option<String>,
+    /// The scopes granted to the token, separated by spaces. Scopes of other APIs are ignored.
+    scope: Option<String>,
+}
+
+/// The least time between fetches of the key set, so tokens with made up key ids can't make the server refetch it.
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Validates JWT bearer tokens against the keys of a JSON Web Key Set.
+///
+/// Keys are rotated by adding them to the key set, so the key set is loaded again when a token's key id is unknown.
+#[derive(Clone)]
+pub struct Authenticator {
+    keys: Arc<RwLock<Arc<JwkSet>>>,
+    /// The file path or URL the key set was loaded from, if any.
+    source: Option<String>,
+    /// When the key set was last loaded. Locked while it is loaded, so concurrent requests load it once.
+    loaded_at: Arc<Mutex<Instant>>,
+    min_refetch_interval: Duration,
+    issuer: Option<String>,
+    audience: Option<String>,
+    /// The scopes granted to tokens without a `scope` claim.
+    scopes: Vec<Scope>,
+}
+
+impl Authenticator {
+    pub fn new(keys: JwkSet, issuer: Option<String>, audience: Option<String>, scopes: Vec<Scope>) -> Self {
+        Self {
+            keys: Arc::new(RwLock::new(Arc::new(keys))),
+            source: None,
+            loaded_at: Arc::new(Mutex::new(Instant::now())),
+            min_refetch_interval: MIN_REFETCH_INTERVAL,
+            issuer,
+            audience,
+            scopes,
+        }
+    }
+
+    /// Loads the key set from a file path, or fetches it when given an HTTP(S) URL.
+    pub async fn load(
+        jwks: &str,
+        issuer: Option<String>,
+        audience: Option<String>,
+        scopes: Vec<Scope>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            source: Some(jwks.to_string()),
+            ..Self::new(fetch(jwks).await?, issuer, audience, scopes)
+        })
+    }
+
+    fn keys(&self) -> Arc<JwkSet> {
+        self.keys.read().unwrap().clone()
+    }
+
+    /// Loads the key set again, unless it was loaded less than the minimum interval ago.
+    async fn refetch(&self) {
+        let Some(source) = &self.source else {
+            return;
+        };
+        let mut loaded_at = self.loaded_at.lock().await;
+        if loaded_at.elapsed() < self.min_refetch_interval {
+            return;
+        }
+
+        *loaded_at = Instant::now();
+        match fetch(source).await {
+            Ok(keys) => *self.keys.write().unwrap() = Arc::new(keys),
+            Err(e) => tracing::warn!(%e, "Failed to refetch the JSON Web Key Set"),
+        }
+    }
+
+    /// The principal of a valid token. Its tenant is taken from the `cognito_id` claim or otherwise the subject,
+    /// and its scopes from the `scope` claim or otherwise the configured scopes.
+    pub async fn principal_of(&self, token: &str) -> anyhow::Result<Principal> {
+        let header = decode_header(token)?;
+        if let Some(kid) = &header.kid
+            && self.keys().find(kid).is_none()
+        {
+            self.refetch().await;
+        }
+
+        let keys = self.keys();
+        let jwk = match &header.kid {
+            Some(kid) => keys.find(kid),
+            // Tokens without a key id can only be matched when there is a single key.
+            None if keys.keys.len() == 1 => keys.keys.first(),
+            None => None,
+        }
+        .ok_or_else(|| anyhow!("No key matches the token"))?;
+
+        // The algorithm is taken from the key rather than the token, so a token can't choose how it is verified.
+        let Some(key_algorithm) = jwk.common.key_algorithm else {
+            bail!("The key doesn't name its algorithm");
+        };
+        let algorithm = Algorithm::from_str(&key_algorithm.to_string())?;
+        if header.alg != algorithm {
+            bail!("The token is signed with {:?}, but its key is for {algorithm:?}", header.alg);
+        }
+
+        let mut validation = Validation::new(algorithm);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let claims = decode::<Claims>(token, &DecodingKey::from_jwk(jwk)?, &validation)?.claims;
+        let tenant = match claims.cognito_id.or(claims.sub) {
+            Some(tenant) if !tenant.is_empty() => tenant,
+            _ => bail!("The token has neither a subject nor a cognito_id claim"),
+        };
+        let scopes = match claims.scope {
+            Some(scope) => scope.split_whitespace().filter_map(|name| name.parse().ok()).collect(),
+            None => self.scopes.clone(),
+        };
+
+        Ok(Principal::user(tenant, scopes))
+    }
+}
+
+/// Reads a key set from a file path, or fetches it from an HTTP(S) URL.
+async fn fetch(jwks: &str) -> anyhow::Result<JwkSet> {
+    if jwks.starts_with("http://") || jwks.starts_with("https://") {
+        Ok(reqwest::get(jwks).await?.error_for_status()?.json().await?)
+    } else {
+        Ok(serde_json::from_str(&tokio::fs::read_to_string(jwks).await?)?)
+    }
+}
+
+/// The ways requests can authenticate.
+#[derive(Clone)]
+pub struct Authentication {
+    /// Validates bearer tokens, if they are accepted.
+    pub tokens: Option<Authenticator>,
+    /// Looks up API keys, which are always accepted.
+    pub keys: AssetRepository,
+}
+
+impl Authentication {
+    /// Whether the headers carry an API key or an authorization, valid or not.
+    pub fn has_credentials(headers: &HeaderMap) -> bool {
+        headers.contains_key(keys::HEADER) || headers.contains_key(header::AUTHORIZATION)
+    }
+
+    /// The principal of an API key or bearer token in the headers, with keys taking precedence.
+    pub async fn principal_of(&self, headers: &HeaderMap) -> anyhow::Result<Principal> {
+        let secret = headers.get(keys::HEADER).map(|secret| secret.to_str()).transpose()?;
+        let authorization = headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+
+        self.principal_from(secret, authorization).await
+    }
+
+    /// The principal of an API key or bearer token in the `connection_init` payload of a subscription,
+    /// given under the same names as the headers, in any case.
+    pub async fn principal_of_payload(&self, payload: &serde_json::Value) -> anyhow::Result<Principal> {
+        let field = |name: &str| {
+            payload
+                .as_object()?
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .and_then(|(_, value)| value.as_str())
+        };
+
+        self.principal_from(field(keys::HEADER), field(header::AUTHORIZATION.as_str()))
+            .await
+    }
+
+    async fn principal_from(&self, secret: Option<&str>, authorization: Option<&str>) -> anyhow::Result<Principal> {
+        if let Some(secret) = secret {
+            let key = self
+                .keys
+                .find_api_key(&keys::hash_of(secret))?
+                .ok_or_else(|| anyhow!("Unknown or revoked API key"))?;
+
+            return Ok(Principal {
+                tenant: key.tenant_id,
+                scopes: key.scopes,
+                key_id: Some(key.id),
+            });
+        }
+
+        let token = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| anyhow!("Neither an API key nor a bearer token was given"))?;
+        let authenticator = self
+            .tokens
+            .as_ref()
+            .ok_or_else(|| anyhow!("Bearer tokens are not accepted"))?;
+
+        authenticator.principal_of(token).await
+    }
+}
+
+/// Rejects requests without a valid API key or bearer token and adds their principal to the request.
+pub async fn authenticate(State(authentication): State<Authentication>, mut request: Request, next: Next) -> Response {
+    match authentication.principal_of(request.headers()).await {
+        Ok(principal) => {
+            request.extensions_mut().insert(principal);
+            next.run(request).await
+        }
+        Err(e) => unauthorized(e),
+    }
+}
+
+/// The rejection of a request without a valid API key or bearer token.
+pub fn unauthorized(e: anyhow::Error) -> Response {
+    tracing::debug!(%e, "Rejected unauthenticated request");
+    (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")]).into_response()
+}
+
+/// The rejection of a principal that lacks a scope.
+#[derive(Debug)]
+pub struct Forbidden(Scope);
+
+impl IntoResponse for Forbidden {
+    fn into_response(self) -> Response {
+        (StatusCode::FORBIDDEN, axum::Json(vec![format!("The {} scope is required", self.0)])).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+    use serde_json::json;
+
+    const SECRET: &[u8] = b"a local secret for signing test tokens";
+
+    fn key_set(kid: &str) -> serde_json::Value {
+        json!({
+            "keys": [{ "kty": "oct", "kid": kid, "alg": "HS256", "k": URL_SAFE_NO_PAD.encode(SECRET) }]
+        })
+    }
+
+    fn authenticator() -> Authenticator {
+        Authenticator::new(
+            serde_json::from_value(key_set("test")).unwrap(),
+            Some("https://issuer".to_string()),
+            None,
+            vec![Scope::AssetsRead],
+        )
+    }
+
+    fn token(claims: serde_json::Value, secret: &[u8]) -> String {
+        signed(Algorithm::HS256, claims, secret)
+    }
+
+    fn signed(algorithm: Algorithm, claims: serde_json::Value, secret: &[u8]) -> String {
+        let mut header = Header::new(algorithm);
+        header.kid = Some("test".to_string());
+
+        encode(&header, &claims, &EncodingKey::from_secret(secret)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn tenants_are_taken_from_valid_tokens() {
+        let authenticator = authenticator();
+        let exp = chrono::Utc::now().timestamp() + 60;
+
+        let by_cognito_id = token(json!({ "sub": "user", "cognito_id": "tenant", "iss": "https://issuer", "exp": exp }), SECRET);
+        assert_eq!(Some("tenant".to_string()), authenticator.principal_of(&by_cognito_id).await.unwrap().tenant);
+
+        let by_subject = token(json!({ "sub": "user", "iss": "https://issuer", "exp": exp }), SECRET);
+        assert_eq!(Some("user".to_string()), authenticator.principal_of(&by_subject).await.unwrap().tenant);
+
+        let expired = token(json!({ "sub": "user", "iss": "https://issuer", "exp": exp - 3600 }), SECRET);
+        assert!(authenticator.principal_of(&expired).await.is_err());
+
+        let other_issuer = token(json!({ "sub": "user", "iss": "https://other", "exp": exp }), SECRET);
+        assert!(authenticator.principal_of(&other_issuer).await.is_err());
+
+        let forged = token(json!({ "sub": "user", "iss": "https://issuer", "exp": exp }), b"another secret");
+        assert!(authenticator.principal_of(&forged).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn tokens_are_verified_with_the_algorithm_of_their_key() {
+        let claims = json!({ "sub": "user", "iss": "https://issuer", "exp": chrono::Utc::now().timestamp() + 60 });
+        assert!(authenticator().principal_of(&signed(Algorithm::HS384, claims.clone(), SECRET)).await.is_err());
+
+        let mut keys = key_set("test");
+        keys["keys"][0].as_object_mut().unwrap().remove("alg");
+        let without_algorithm = Authenticator::new(serde_json::from_value(keys).unwrap(), None, None, vec![]);
+        assert!(without_algorithm.principal_of(&token(claims, SECRET)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn scopes_are_taken_from_the_scope_claim() {
+        let authenticator = authenticator();
+        let exp = chrono::Utc::now().timestamp() + 60;
+        let scopes = |scope: Option<&str>| {
+            let mut claims = json!({ "sub": "user", "iss": "https://issuer", "exp": exp });
+            if let Some(scope) = scope {
+                claims["scope"] = json!(scope);
+            }
+            let authenticator = authenticator.clone();
+            async move { authenticator.principal_of(&token(claims, SECRET)).await.unwrap().scopes }
+        };
+
+        assert_eq!(vec![Scope::AssetsRead], scopes(None).await);
+        assert_eq!(vec![Scope::ImportWrite], scopes(Some("openid import:write")).await);
+        assert_eq!(Vec::<Scope>::new(), scopes(Some("admin")).await);
+    }
+
+    #[tokio::test]
+    async fn key_sets_are_reloaded_for_unknown_key_ids() {
+        let path = std::env::temp_dir().join(format!("assets-{}-jwks.json", std::process::id()));
+        std::fs::write(&path, key_set("old").to_string()).unwrap();
+        let mut authenticator = Authenticator::load(path.to_str().unwrap(), None, None, vec![]).await.unwrap();
+        let rotated = token(json!({ "sub": "user", "exp": chrono::Utc::now().timestamp() + 60 }), SECRET);
+
+        // The key set was just loaded, so it isn't loaded again yet.
+        std::fs::write(&path, key_set("test").to_string()).unwrap();
+        assert!(authenticator.principal_of(&rotated).await.is_err());
+
+        authenticator.min_refetch_interval = Duration::ZERO;
+        assert!(authenticator.principal_of(&rotated).await.is_ok());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
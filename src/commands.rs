@@ -1,31 +1,139 @@
 use std::path::PathBuf;
+use std::time::Duration;
 use anyhow::anyhow;
+use serde::Serialize;
 use tokio::io::AsyncReadExt;
-use crate::import;
-use crate::options::ImportOptions;
+use crate::{api, db, identity, import, keys, schema};
+use crate::db::AssetRepository;
+use crate::options::{
+    CreateKeyOptions, Credentials, DiffOptions, ImportOptions, KeysServerOptions, MergeOptions, RevokeKeyOptions,
+    SplitOptions,
+};
 
 pub async fn import_assets(import_options: &ImportOptions) -> anyhow::Result<()> {
     // We could also chunk the assets into smaller batches if needed.
     let contents = read_assets(import_options.path.as_ref()).await?;
     let assets: Vec<import::Asset> = serde_json::from_str(&contents)?;
 
-    let client = reqwest::Client::new();
-    let response = client
-        .post(import_options.uri.as_str())
+    post(&import_options.uri, &assets, &import_options.credentials, "importing assets").await
+}
+
+pub async fn merge_identities(merge_options: &MergeOptions) -> anyhow::Result<()> {
+    let request = identity::MergeRequest {
+        target: merge_options.target.clone(),
+        source: merge_options.source.clone(),
+    };
+
+    post(&merge_options.uri, &request, &merge_options.credentials, "merging identities").await
+}
+
+pub async fn split_identity(split_options: &SplitOptions) -> anyhow::Result<()> {
+    let request = identity::SplitRequest {
+        asset_id: split_options.asset_id.clone(),
+    };
+
+    post(&split_options.uri, &request, &split_options.credentials, "splitting identity").await
+}
+
+pub fn print_schema() -> anyhow::Result<()> {
+    println!("{}", api::sdl());
+    Ok(())
+}
+
+pub async fn diff_schema(diff_options: &DiffOptions) -> anyhow::Result<()> {
+    let old = tokio::fs::read_to_string(&diff_options.old).await?;
+    let changes = schema::diff(&old, &api::sdl())?;
+
+    for change in &changes {
+        println!("{change}");
+    }
+
+    let breaking = changes
+        .iter()
+        .filter(|change| change.severity == schema::Severity::Breaking)
+        .count();
+    if breaking > 0 {
+        return Err(anyhow!("Found {breaking} breaking schema changes"));
+    }
+
+    Ok(())
+}
+
+pub async fn create_key(create_options: &CreateKeyOptions) -> anyhow::Result<()> {
+    let request = keys::CreateKeyRequest {
+        name: create_options.name.clone(),
+        scopes: create_options.scopes.clone(),
+        tenant_id: create_options.tenant.clone(),
+    };
+
+    let created: keys::CreatedKey = match &create_options.database_path {
+        Some(database_path) => {
+            let asset_repository = AssetRepository::new(database_path, 1, Duration::from_secs(10))?;
+            asset_repository.setup()?;
+
+            let created = keys::generate(request)?;
+            asset_repository.create_api_key(&created.key, &keys::hash_of(&created.secret))?;
+            created
+        }
+        None => {
+            let server = &create_options.server;
+            let request = reqwest::Client::new().post(server.uri.as_str()).json(&request);
+            serde_json::from_str(&send(request, &server.credentials, "creating API key").await?)?
+        }
+    };
+
+    println!("{}", serde_json::to_string_pretty(&created)?);
+    Ok(())
+}
+
+pub async fn list_keys(server_options: &KeysServerOptions) -> anyhow::Result<()> {
+    let request = reqwest::Client::new().get(server_options.uri.as_str());
+    let keys: Vec<db::ApiKey> = serde_json::from_str(&send(request, &server_options.credentials, "listing API keys").await?)?;
+
+    println!("{}", serde_json::to_string_pretty(&keys)?);
+    Ok(())
+}
+
+pub async fn revoke_key(revoke_options: &RevokeKeyOptions) -> anyhow::Result<()> {
+    let server = &revoke_options.server;
+    let uri = format!("{}/{}", server.uri.as_str().trim_end_matches('/'), revoke_options.id);
+
+    send(reqwest::Client::new().delete(uri), &server.credentials, "revoking API key").await?;
+    Ok(())
+}
+
+async fn post<T: Serialize + ?Sized>(
+    uri: &reqwest::Url,
+    payload: &T,
+    credentials: &Credentials,
+    action: &str,
+) -> anyhow::Result<()> {
+    let request = reqwest::Client::new()
+        .post(uri.as_str())
         .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .json(&assets)
-        .send()
-        .await?;
+        .json(payload);
+
+    send(request, credentials, action).await?;
+    Ok(())
+}
+
+/// Sends a request with the credentials, returning the body of a successful response.
+async fn send(request: reqwest::RequestBuilder, credentials: &Credentials, action: &str) -> anyhow::Result<String> {
+    let request = match &credentials.api_key {
+        Some(api_key) => request.header(keys::HEADER, api_key),
+        None => request,
+    };
+    let response = request.send().await?;
 
     let status = response.status();
     let body = response.text().await?;
 
     if status.is_success() {
-        tracing::debug!(%body, %status, "Finished importing assets");
-        Ok(())
+        tracing::debug!(%body, %status, "Finished {action}");
+        Ok(body)
     } else {
-        tracing::error!(%body, %status, "Failed to import assets");
-        Err(anyhow!("Failed to import assets"))
+        tracing::error!(%body, %status, "Failed {action}");
+        Err(anyhow!("Failed {action}"))
     }
 }
 
@@ -0,0 +1,169 @@
+use duckdb::ToSql;
+
+use super::{Asset, PrimaryAssetCategory, WealthAssetType};
+
+/// Restricts the assets returned by balance queries, based on the attributes of their latest snapshot.
+///
+/// Every condition that is set must match. An `Unknown` category or type matches every value
+/// that is not known to this service, regardless of its raw value.
+#[derive(Debug, Default)]
+pub struct BalanceFilter {
+    pub categories: Option<Vec<PrimaryAssetCategory>>,
+    pub wealth_asset_types: Option<Vec<WealthAssetType>>,
+    pub is_active: Option<bool>,
+    pub is_favorite: Option<bool>,
+    pub include_in_net_worth: Option<bool>,
+    pub is_asset: Option<bool>,
+    pub min_balance: Option<f64>,
+    pub max_balance: Option<f64>,
+    /// A case-insensitive substring of the nickname.
+    pub nickname: Option<String>,
+}
+
+impl BalanceFilter {
+    /// A SQL boolean expression over the columns of `latest_snapshots`, along with the parameters it binds in order.
+    pub fn to_sql(&self) -> (String, Vec<Box<dyn ToSql>>) {
+        let mut conditions = vec!["TRUE".to_string()];
+        let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+        if let Some(categories) = &self.categories {
+            conditions.push(one_of("primary_asset_category", categories, &mut params));
+        }
+        if let Some(types) = &self.wealth_asset_types {
+            conditions.push(one_of("wealth_asset_type", types, &mut params));
+        }
+
+        let flags = [
+            ("is_active", self.is_active),
+            ("is_favorite", self.is_favorite),
+            ("include_in_net_worth", self.include_in_net_worth),
+            ("is_asset", self.is_asset),
+        ];
+        for (column, flag) in flags {
+            if let Some(flag) = flag {
+                conditions.push(format!("{column} = ?"));
+                params.push(Box::new(flag));
+            }
+        }
+
+        if let Some(min_balance) = self.min_balance {
+            conditions.push("balance_current >= ?".to_string());
+            params.push(Box::new(min_balance));
+        }
+        if let Some(max_balance) = self.max_balance {
+            conditions.push("balance_current <= ?".to_string());
+            params.push(Box::new(max_balance));
+        }
+        if let Some(nickname) = &self.nickname {
+            conditions.push("contains(lower(nickname), lower(?))".to_string());
+            params.push(Box::new(nickname.clone()));
+        }
+
+        (conditions.join(" AND "), params)
+    }
+
+    /// Whether a single snapshot matches the filter, following the same rules as `to_sql`.
+    pub fn matches(&self, asset: &Asset) -> bool {
+        let flags = [
+            (self.is_active, asset.is_active),
+            (self.is_favorite, asset.is_favorite),
+            (self.include_in_net_worth, asset.include_in_net_worth),
+            (self.is_asset, asset.is_asset),
+        ];
+
+        self.categories
+            .as_ref()
+            .is_none_or(|categories| is_one_of(&asset.primary_asset_category, categories))
+            && self
+                .wealth_asset_types
+                .as_ref()
+                .is_none_or(|types| is_one_of(&asset.wealth_asset_type, types))
+            && flags
+                .into_iter()
+                .all(|(flag, value)| flag.is_none_or(|flag| flag == value))
+            && self
+                .min_balance
+                .is_none_or(|min_balance| asset.balance_current >= min_balance)
+            && self
+                .max_balance
+                .is_none_or(|max_balance| asset.balance_current <= max_balance)
+            && self.nickname.as_ref().is_none_or(|nickname| {
+                asset
+                    .nickname
+                    .to_lowercase()
+                    .contains(&nickname.to_lowercase())
+            })
+    }
+}
+
+/// An enumeration stored as text that may hold values unknown to this service.
+trait Enumeration: ToSql + Clone + Sized + 'static {
+    const KNOWN: &'static [Self];
+
+    fn is_unknown(&self) -> bool;
+}
+
+impl Enumeration for PrimaryAssetCategory {
+    const KNOWN: &'static [Self] = &[
+        PrimaryAssetCategory::Cash,
+        PrimaryAssetCategory::Investment,
+        PrimaryAssetCategory::RealEstate,
+        PrimaryAssetCategory::OtherProperty,
+    ];
+
+    fn is_unknown(&self) -> bool {
+        matches!(self, PrimaryAssetCategory::Unknown(_))
+    }
+}
+
+impl Enumeration for WealthAssetType {
+    const KNOWN: &'static [Self] = &[
+        WealthAssetType::Brokerage,
+        WealthAssetType::Cash,
+        WealthAssetType::Cryptocurrency,
+        WealthAssetType::RealEstate,
+        WealthAssetType::Vehicle,
+    ];
+
+    fn is_unknown(&self) -> bool {
+        matches!(self, WealthAssetType::Unknown(_))
+    }
+}
+
+fn one_of<T: Enumeration>(column: &str, values: &[T], params: &mut Vec<Box<dyn ToSql>>) -> String {
+    let mut alternatives = vec!["FALSE".to_string()];
+    let known: Vec<_> = values.iter().filter(|v| !v.is_unknown()).collect();
+
+    if !known.is_empty() {
+        alternatives.push(format!("{column} IN ({})", placeholders(known.len())));
+        params.extend(
+            known
+                .into_iter()
+                .map(|v| Box::new(v.clone()) as Box<dyn ToSql>),
+        );
+    }
+    if values.iter().any(T::is_unknown) {
+        alternatives.push(format!(
+            "{column} NOT IN ({})",
+            placeholders(T::KNOWN.len())
+        ));
+        params.extend(
+            T::KNOWN
+                .iter()
+                .map(|v| Box::new(v.clone()) as Box<dyn ToSql>),
+        );
+    }
+
+    format!("({})", alternatives.join(" OR "))
+}
+
+/// Whether the value is one of the given values. Every unknown value is considered equal to `Unknown`.
+fn is_one_of<T: Enumeration>(value: &T, values: &[T]) -> bool {
+    values
+        .iter()
+        .any(|v| std::mem::discriminant(v) == std::mem::discriminant(value))
+}
+
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
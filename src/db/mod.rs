@@ -1,20 +1,44 @@
+mod filter;
 mod model;
+mod order;
 
-use std::path::Path;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use chrono::{DateTime, Utc};
-use duckdb::{params, DuckdbConnectionManager};
+use anyhow::bail;
+use duckdb::{params, params_from_iter, AccessMode, Config, DuckdbConnectionManager, ToSql};
+use r2d2::PooledConnection;
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast;
 
+use crate::metrics;
+
+pub use filter::*;
 pub use model::*;
+pub use order::*;
 
 /// Repository for managing assets in the DuckDB database.
 #[derive(Clone)]
 pub struct AssetRepository {
     // DuckDB is not async, but this is fine for a local application.
-    pool: r2d2::Pool<DuckdbConnectionManager>,
+    // The pool is swapped when a read-only database is reloaded. Connections taken from the old pool keep it alive
+    // until they are returned, so queries that are running when the database is reloaded finish on the old file.
+    pool: Arc<RwLock<r2d2::Pool<DuckdbConnectionManager>>>,
     pool_timeout: Duration,
+    events: broadcast::Sender<Committed>,
+    /// The tenant whose data the repository is scoped to. Unscoped repositories see the data of every tenant.
+    tenant: Option<String>,
+    /// The file a read-only repository was opened from, so it can be reopened when the file is replaced.
+    read_only_path: Option<Arc<PathBuf>>,
 }
 
+/// The number of committed batches a subscriber can fall behind by before it misses some.
+const EVENT_CAPACITY: usize = 256;
+
+/// The version of `structure.sql`. Bump it whenever the structure changes in a way older servers or databases
+/// aren't compatible with.
+pub const SCHEMA_VERSION: i32 = 1;
+
 impl AssetRepository {
     /// Creates a new instance of the `AssetsRepository`.
     pub fn new(
@@ -27,96 +51,1463 @@ impl AssetRepository {
             .max_size(max_connections)
             .build(manager)?;
 
-        Ok(Self { pool, pool_timeout })
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+
+        Ok(Self {
+            pool: Arc::new(RwLock::new(pool)),
+            pool_timeout,
+            events,
+            tenant: None,
+            read_only_path: None,
+        })
+    }
+
+    /// Opens an existing database without write access, so other processes may open it read-only too.
+    /// The database must have been set up by a read-write repository, and changes made through the repository fail.
+    pub fn open_read_only(
+        path: impl AsRef<Path>,
+        max_connections: u32,
+        pool_timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let pool = read_only_pool(&path, max_connections)?;
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+
+        Ok(Self {
+            pool: Arc::new(RwLock::new(pool)),
+            pool_timeout,
+            events,
+            tenant: None,
+            read_only_path: Some(Arc::new(path)),
+        })
+    }
+
+    /// Reopens a read-only database, so queries see the file that is at its path now.
+    /// The new pool is only swapped in once the file was opened successfully.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.read_only_path else {
+            bail!("Only read-only databases can be reloaded");
+        };
+
+        let max_connections = self.current_pool().max_size();
+        let pool = read_only_pool(path, max_connections)?;
+        *self.pool.write().unwrap_or_else(|e| e.into_inner()) = pool;
+
+        Ok(())
+    }
+
+    /// Copies a consistent snapshot of the database to a file, replacing it atomically so readers of the file
+    /// never see a partial copy.
+    pub fn copy_to(&self, path: &Path) -> anyhow::Result<()> {
+        let _timer = metrics::time_query("copy_to");
+        let mut staging = path.as_os_str().to_os_string();
+        staging.push(".staging");
+        let staging = PathBuf::from(staging);
+        remove_database_file(&staging)?;
+
+        let connection = self.connection()?;
+        let database: String = connection.query_row("SELECT current_database()", [], |row| row.get(0))?;
+        connection.execute_batch(&format!(
+            "ATTACH '{}' AS staging; COPY FROM DATABASE \"{}\" TO staging; DETACH staging;",
+            staging.display().to_string().replace('\'', "''"),
+            database.replace('"', "\"\""),
+        ))?;
+
+        std::fs::rename(&staging, path)?;
+        Ok(())
+    }
+
+    fn current_pool(&self) -> r2d2::Pool<DuckdbConnectionManager> {
+        self.pool.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn connection(&self) -> anyhow::Result<PooledConnection<DuckdbConnectionManager>> {
+        let started = Instant::now();
+        let connection = self.current_pool().get_timeout(self.pool_timeout);
+        metrics::observe_pool_wait(started.elapsed());
+
+        Ok(connection?)
     }
 
-    /// Creates the necessary database structure.
+    /// The number of open and idle connections in the pool.
+    pub fn pool_state(&self) -> r2d2::State {
+        self.current_pool().state()
+    }
+
+    /// A repository that only reads and records the data of the given tenant.
+    pub fn scoped(&self, tenant: Option<String>) -> Self {
+        Self {
+            tenant,
+            ..self.clone()
+        }
+    }
+
+    /// The tenant the repository is scoped to, if any.
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    /// Subscribes to the batches of snapshots committed from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Committed> {
+        self.events.subscribe()
+    }
+
+    /// Publishes a committed batch of snapshots to the current subscribers.
+    fn publish(&self, origin: Origin, recorded_at: DateTime<Utc>, snapshots: Vec<Asset>, recorded: &[Recorded]) {
+        // Sending only fails when nobody is subscribed, which is fine.
+        let _ = self.events.send(Committed {
+            recorded_at,
+            origin,
+            snapshots: Arc::new(snapshots),
+            recorded: Arc::new(recorded.to_vec()),
+        });
+    }
+
+    /// Creates the necessary database structure, migrating databases created by earlier versions.
+    ///
+    /// The schema version is only recorded once the migrations to it have run, so a database that failed to migrate
+    /// keeps its old version and is reported as not ready.
     pub fn setup(&self) -> anyhow::Result<()> {
-        let connection = self.pool.get_timeout(self.pool_timeout)?;
+        let _timer = metrics::time_query("setup");
+        let version = self.schema_version()?;
+        if let Some(version) = version.filter(|version| *version > SCHEMA_VERSION) {
+            bail!("The database was set up with schema version {version}, which is newer than {SCHEMA_VERSION}");
+        }
+
+        let mut connection = self.connection()?;
+        // Outside a transaction every statement commits on its own, which DuckDB needs to update a table it just altered.
         let sql = include_str!("sql/structure.sql");
         connection.execute_batch(sql)?;
+
+        let unversioned: bool = connection.query_row(include_str!("sql/unversioned_assets.sql"), [], |row| row.get(0))?;
+        if unversioned {
+            let tx = connection.transaction()?;
+            tx.execute_batch(include_str!("sql/rebuild_assets.sql"))?;
+            tx.commit()?;
+        }
+
+        if version != Some(SCHEMA_VERSION) {
+            let tx = connection.transaction()?;
+            tx.execute("DELETE FROM schema_version", [])?;
+            tx.execute("INSERT INTO schema_version (version) VALUES (?)", params![SCHEMA_VERSION])?;
+            tx.commit()?;
+        }
+
         Ok(())
     }
 
+    /// Checks that a connection can be acquired and used within a deadline, regardless of the pool timeout.
+    pub fn ping(&self, deadline: Duration) -> anyhow::Result<()> {
+        let connection = self.current_pool().get_timeout(deadline)?;
+        connection.execute_batch("SELECT 1")?;
+        Ok(())
+    }
+
+    /// The version of the structure the database was set up with, or `None` for databases set up before it was versioned
+    /// and for those whose snapshots haven't been migrated to be versioned.
+    pub fn schema_version(&self) -> anyhow::Result<Option<i32>> {
+        let _timer = metrics::time_query("schema_version");
+        let connection = self.connection()?;
+        let versioned: bool = connection.query_row(
+            "SELECT count(*) > 0 FROM duckdb_tables() WHERE table_name = 'schema_version'",
+            [],
+            |row| row.get(0),
+        )?;
+        let unversioned_assets: bool =
+            connection.query_row(include_str!("sql/unversioned_assets.sql"), [], |row| row.get(0))?;
+        if !versioned || unversioned_assets {
+            return Ok(None);
+        }
+
+        Ok(connection.query_row(include_str!("sql/schema_version.sql"), [], |row| row.get(0))?)
+    }
+
+    /// When the most recent snapshot of any tenant was recorded, or `None` when there are no snapshots.
+    pub fn last_recorded_at(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let _timer = metrics::time_query("last_recorded_at");
+        let connection = self.connection()?;
+        Ok(connection.query_row(include_str!("sql/last_recorded_at.sql"), [], |row| row.get(0))?)
+    }
+
 
     /// Inserts multiple assets into the database.
-    pub fn insert(&self, assets: Vec<Asset>) -> anyhow::Result<()> {
-        let mut connection = self.pool.get_timeout(self.pool_timeout)?;
+    ///
+    /// All assets are recorded at the same system time. Re-importing a snapshot with the same
+    /// `asset_id` and `balance_as_of` supersedes the previously recorded version instead of
+    /// overwriting it, so past answers remain reproducible via `known_at`. A snapshot may only appear once
+    /// per batch, since its versions would be recorded at the same system time.
+    ///
+    /// Asset ids seen for the first time are registered under the asset's identity and the repository's tenant,
+    /// or the asset's own tenant when the repository is not scoped to one.
+    /// Asset ids that are already known keep their current identity, which may have been changed by a merge or split.
+    /// Recording an asset id of another tenant fails the whole batch.
+    ///
+    /// Returns whether each asset was inserted or updated, in the order they were given.
+    pub fn insert(&self, mut assets: Vec<Asset>) -> anyhow::Result<Vec<Recorded>> {
+        let _timer = metrics::time_query("insert");
+        let mut connection = self.connection()?;
         let tx = connection.transaction()?;
-        let query = include_str!("sql/insert.sql");
-
-        tx.prepare(query)?;
-
-        for asset in assets {
-            tx.execute(
-                query,
-                params![
-                    asset.asset_id,
-                    asset.balance_as_of,
-                    asset.balance_current,
-                    asset.creation_date,
-                    asset.deactivate_by,
-                    asset.include_in_net_worth,
-                    asset.is_active,
-                    asset.is_asset,
-                    asset.is_favorite,
-                    asset.last_update,
-                    asset.last_update_attempt,
-                    asset.modification_date,
-                    asset.nickname,
-                    asset.primary_asset_category,
-                    asset.wealth_asset_type,
-                    asset.wid,
-                ],
-            )?;
+        let recorded_at = Utc::now();
+        let mut recorded = Vec::with_capacity(assets.len());
+
+        for asset in &mut assets {
+            recorded.push(insert_snapshot(&tx, asset, self.tenant(), recorded_at)?);
         }
 
         tx.commit()?;
+        self.publish(Origin::Import, recorded_at, assets, &recorded);
+
+        Ok(recorded)
+    }
+
+    /// Records a snapshot produced by a change outside of imports, along with an entry in the audit trail.
+    ///
+    /// The snapshot is recorded like an imported one, and the audit entry shares its system time.
+    pub fn insert_audited(&self, mut asset: Asset, action: &str, details: &str) -> anyhow::Result<()> {
+        let _timer = metrics::time_query("insert_audited");
+        let mut connection = self.connection()?;
+        let tx = connection.transaction()?;
+        let recorded_at = Utc::now();
+
+        let recorded = insert_snapshot(&tx, &mut asset, self.tenant(), recorded_at)?;
+        tx.execute(
+            include_str!("sql/insert_audit_entry.sql"),
+            params![recorded_at, action, asset.asset_id, asset.balance_as_of, details],
+        )?;
+
+        tx.commit()?;
+        self.publish(Origin::Change, recorded_at, vec![asset], &[recorded]);
 
         Ok(())
     }
 
-    /// The balances of all assets as of a specific date.
+    /// The changes made outside of imports to any asset id grouped under an identity, oldest first.
+    pub fn audit_trail(&self, identity_id: &str) -> anyhow::Result<Vec<AuditEntry>> {
+        let _timer = metrics::time_query("audit_trail");
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(include_str!("sql/audit_trail.sql"))?;
+        let entries = statement.query_map(params![identity_id, self.tenant], |row| {
+            Ok(AuditEntry {
+                recorded_at: row.get(0)?,
+                action: row.get(1)?,
+                asset_id: row.get(2)?,
+                balance_as_of: row.get(3)?,
+                details: row.get(4)?,
+            })
+        })?;
+
+        Ok(entries.collect::<Result<_, _>>()?)
+    }
+
+    /// The balances of all assets matching the filter as of a specific date, as they were known at a given system time.
+    ///
+    /// Balances are sorted by the given order, then by nickname and identity. Pages are bounded by
+    /// keyset predicates on the sort order instead of offsets, so rows are neither skipped nor repeated.
     pub fn balances(
         &self,
         as_of: DateTime<Utc>,
+        known_at: DateTime<Utc>,
+        filter: &BalanceFilter,
+        order: &[BalanceOrder],
+        page: &BalancePage,
+    ) -> anyhow::Result<Vec<Asset>> {
+        let _timer = metrics::time_query("balances");
+        let (condition, filter_params) = filter.to_sql();
+        let mut params: Vec<Box<dyn ToSql>> = vec![Box::new(self.tenant.clone()), Box::new(as_of), Box::new(known_at)];
+        params.extend(filter_params);
+
+        let mut bounds = vec!["TRUE".to_string()];
+        if let Some(after) = &page.after {
+            let (bound, bound_params) = keyset_sql(order, after, true)?;
+            bounds.push(bound);
+            params.extend(bound_params);
+        }
+        if let Some(before) = &page.before {
+            let (bound, bound_params) = keyset_sql(order, before, false)?;
+            bounds.push(bound);
+            params.extend(bound_params);
+        }
+        params.push(Box::new(page.limit));
+
+        let connection = self.connection()?;
+        let query = include_str!("sql/balances.sql")
+            .replace("{filter}", &condition)
+            .replace("{keyset}", &bounds.join(" AND "))
+            .replace("{order}", &order_by_sql(order, page.from_end));
+        let mut statement = connection.prepare(&query)?;
+        let assets = statement.query_map(params_from_iter(params), map_row_to_asset)?;
+        let mut assets = assets.collect::<Result<Vec<_>, _>>()?;
+
+        if page.from_end {
+            assets.reverse();
+        }
+
+        Ok(assets)
+    }
+
+    /// The latest snapshot as of a specific date of the identity that an asset id is grouped under,
+    /// as it was known at a given system time.
+    pub fn find_asset(
+        &self,
+        asset_id: &str,
+        as_of: DateTime<Utc>,
+        known_at: DateTime<Utc>,
+    ) -> anyhow::Result<Option<Asset>> {
+        let _timer = metrics::time_query("find_asset");
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(include_str!("sql/find_asset.sql"))?;
+        let mut assets = statement.query_map(params![self.tenant, as_of, known_at, asset_id], map_row_to_asset)?;
+
+        Ok(assets.next().transpose()?)
+    }
+
+    /// The latest snapshot as of a specific date of every identity with a snapshot of a wid,
+    /// as it was known at a given system time.
+    ///
+    /// A wid identifies a user rather than an account, so it may match several identities.
+    pub fn find_assets_by_wid(
+        &self,
+        wid: i128,
+        as_of: DateTime<Utc>,
+        known_at: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Asset>> {
+        let _timer = metrics::time_query("find_assets_by_wid");
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(include_str!("sql/find_assets_by_wid.sql"))?;
+        let assets = statement.query_map(params![self.tenant, as_of, known_at, self.tenant, known_at, wid], map_row_to_asset)?;
+
+        Ok(assets.collect::<Result<_, _>>()?)
+    }
+
+    /// The snapshot of an asset id for a balance date, as it was known at a given system time.
+    pub fn find_snapshot(
+        &self,
+        asset_id: &str,
+        balance_as_of: DateTime<Utc>,
+        known_at: DateTime<Utc>,
+    ) -> anyhow::Result<Option<Asset>> {
+        let _timer = metrics::time_query("find_snapshot");
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(include_str!("sql/find_snapshot.sql"))?;
+        let mut assets = statement.query_map(params![self.tenant, known_at, asset_id, balance_as_of], map_row_to_asset)?;
+
+        Ok(assets.next().transpose()?)
+    }
+
+    /// The snapshots of an identity with a balance date in a range, newest first, as they were known at a given system time.
+    ///
+    /// Pages start after the given key, so snapshots recorded for older dates never shift later pages.
+    pub fn snapshots(
+        &self,
+        identity_id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        known_at: DateTime<Utc>,
+        after: Option<&SnapshotKey>,
         limit: usize,
-        offset: usize,
     ) -> anyhow::Result<Vec<Asset>> {
-        let connection = self.pool.get_timeout(self.pool_timeout)?;
-        let mut statement = connection.prepare(include_str!("sql/balances.sql"))?;
-        let assets = statement.query_map(params![as_of, limit, offset], map_row_to_asset)?;
+        let _timer = metrics::time_query("snapshots");
+        let mut params: Vec<Box<dyn ToSql>> = vec![
+            Box::new(self.tenant.clone()),
+            Box::new(known_at),
+            Box::new(identity_id.to_string()),
+        ];
 
-        Ok(assets.filter_map(Result::ok).collect())
+        let mut range = vec!["TRUE"];
+        if let Some(from) = from {
+            range.push("balance_as_of >= ?");
+            params.push(Box::new(from));
+        }
+        if let Some(to) = to {
+            range.push("balance_as_of <= ?");
+            params.push(Box::new(to));
+        }
+
+        let mut keyset = "TRUE";
+        if let Some(after) = after {
+            keyset = "(balance_as_of < ? OR (balance_as_of = ? AND asset_id < ?))";
+            params.push(Box::new(after.balance_as_of));
+            params.push(Box::new(after.balance_as_of));
+            params.push(Box::new(after.asset_id.clone()));
+        }
+        params.push(Box::new(limit));
+
+        let connection = self.connection()?;
+        let query = include_str!("sql/snapshots.sql")
+            .replace("{range}", &range.join(" AND "))
+            .replace("{keyset}", keyset);
+        let mut statement = connection.prepare(&query)?;
+        let assets = statement.query_map(params_from_iter(params), map_row_to_asset)?;
+
+        Ok(assets.collect::<Result<_, _>>()?)
     }
 
-    /// Count the total balances of all assets as of a specific date.
-    pub fn count_balances(&self, as_of: DateTime<Utc>) -> anyhow::Result<usize> {
-        let connection = self.pool.get_timeout(self.pool_timeout)?;
-        let mut statement = connection.prepare(include_str!("sql/count_balances.sql"))?;
-        let count = statement.query_row(params![as_of], |r| r.get(0))?;
+    /// Count the balances of all assets matching the filter as of a specific date, as they were known at a given system time.
+    pub fn count_balances(
+        &self,
+        as_of: DateTime<Utc>,
+        known_at: DateTime<Utc>,
+        filter: &BalanceFilter,
+    ) -> anyhow::Result<usize> {
+        let _timer = metrics::time_query("count_balances");
+        let (condition, filter_params) = filter.to_sql();
+        let mut params: Vec<Box<dyn ToSql>> = vec![Box::new(self.tenant.clone()), Box::new(as_of), Box::new(known_at)];
+        params.extend(filter_params);
+
+        let connection = self.connection()?;
+        let query = include_str!("sql/count_balances.sql").replace("{filter}", &condition);
+        let mut statement = connection.prepare(&query)?;
+        let count = statement.query_row(params_from_iter(params), |r| r.get(0))?;
 
         Ok(count)
     }
+
+    /// The net worth of all assets included in net worth as of a specific date, as it was known at a given system time.
+    ///
+    /// Balances of liabilities are expected to be positive and are subtracted from the balances of assets.
+    pub fn net_worth(&self, as_of: DateTime<Utc>, known_at: DateTime<Utc>) -> anyhow::Result<NetWorth> {
+        let _timer = metrics::time_query("net_worth");
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(include_str!("sql/net_worth.sql"))?;
+        let mut rows = statement.query(params![self.tenant, as_of, known_at])?;
+        let mut net_worth = NetWorth::default();
+
+        while let Some(row) = rows.next()? {
+            let totals = NetWorthTotals {
+                assets: row.get(2)?,
+                liabilities: row.get(3)?,
+            };
+            let all_categories: bool = row.get(4)?;
+            let all_types: bool = row.get(5)?;
+
+            match (all_categories, all_types) {
+                (true, true) => net_worth.totals = totals,
+                (false, _) => net_worth.by_category.push((row.get(0)?, totals)),
+                (_, false) => net_worth.by_type.push((row.get(1)?, totals)),
+            }
+        }
+
+        Ok(net_worth)
+    }
+
+    /// The change in net worth and in the balance of every asset between two dates, as known at a given system time.
+    pub fn performance(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        known_at: DateTime<Utc>,
+    ) -> anyhow::Result<Performance> {
+        let _timer = metrics::time_query("performance");
+        let starting = self.net_worth(from, known_at)?.totals;
+        let ending = self.net_worth(to, known_at)?.totals;
+
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(include_str!("sql/performance.sql"))?;
+        let assets = statement.query_map(params![from, to, known_at, self.tenant], |row| {
+            Ok(AssetPerformance {
+                identity_id: row.get(0)?,
+                nickname: row.get(1)?,
+                starting_balance: row.get(2)?,
+                ending_balance: row.get(3)?,
+                balance_cost_basis: row.get(4)?,
+            })
+        })?;
+
+        Ok(Performance {
+            starting,
+            ending,
+            assets: assets.collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// The balance of an asset's identity at the close of every bucket in a date range,
+    /// carrying forward the last known balance into buckets without a snapshot.
+    ///
+    /// Returns `None` when the asset id was never recorded for the repository's tenant.
+    pub fn balance_history(
+        &self,
+        asset_id: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        interval: Interval,
+        known_at: DateTime<Utc>,
+    ) -> anyhow::Result<Option<Vec<BalanceBucket>>> {
+        let _timer = metrics::time_query("balance_history");
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(include_str!("sql/identity_of.sql"))?;
+        let owner: Option<Option<String>> = statement
+            .query_map(params![asset_id], |row| row.get(1))?
+            .next()
+            .transpose()?;
+        match owner {
+            Some(owner) if self.tenant.is_none() || owner == self.tenant => {}
+            _ => return Ok(None),
+        }
+
+        let mut statement = connection.prepare(include_str!("sql/balance_history.sql"))?;
+        let buckets = statement.query_map(
+            params![asset_id, from, to, interval.date_part(), known_at, interval.step(), self.tenant],
+            |row| {
+                Ok(BalanceBucket {
+                    starts_at: row.get(0)?,
+                    closes_at: row.get(1)?,
+                    balance: row.get(2)?,
+                    balance_as_of: row.get(3)?,
+                })
+            },
+        )?;
+
+        Ok(Some(buckets.collect::<Result<_, _>>()?))
+    }
+
+    /// The net worth at the close of every bucket in a date range,
+    /// carrying forward the last known balance of each asset into buckets without a snapshot.
+    pub fn net_worth_history(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        interval: Interval,
+        known_at: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<NetWorthBucket>> {
+        let _timer = metrics::time_query("net_worth_history");
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(include_str!("sql/net_worth_history.sql"))?;
+        let buckets = statement.query_map(
+            params![from, to, interval.date_part(), known_at, interval.step(), self.tenant],
+            |row| {
+                Ok(NetWorthBucket {
+                    starts_at: row.get(0)?,
+                    closes_at: row.get(1)?,
+                    totals: NetWorthTotals {
+                        assets: row.get(2)?,
+                        liabilities: row.get(3)?,
+                    },
+                })
+            },
+        )?;
+
+        Ok(buckets.collect::<Result<_, _>>()?)
+    }
+
+    /// The nicknames an asset identity has been known by, in the order they were first used.
+    pub fn nickname_history(&self, identity_id: &str) -> anyhow::Result<Vec<NicknameChange>> {
+        let _timer = metrics::time_query("nickname_history");
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(include_str!("sql/nickname_history.sql"))?;
+        let changes = statement.query_map(params![identity_id, self.tenant], |row| {
+            Ok(NicknameChange {
+                nickname: row.get(0)?,
+                since: row.get(1)?,
+            })
+        })?;
+
+        Ok(changes.collect::<Result<_, _>>()?)
+    }
+
+    /// Merges the history of the source identity into the target identity.
+    ///
+    /// Returns `false` when either identity does not exist.
+    pub fn merge_identities(&self, target: &str, source: &str) -> anyhow::Result<bool> {
+        let _timer = metrics::time_query("merge_identities");
+        if target == source {
+            bail!("Cannot merge identity {target} into itself");
+        }
+
+        let mut connection = self.connection()?;
+        let tx = connection.transaction()?;
+
+        let members: usize = tx.query_row(
+            include_str!("sql/count_identity_members.sql"),
+            params![target, self.tenant],
+            |r| r.get(0),
+        )?;
+        if members == 0 {
+            return Ok(false);
+        }
+
+        let merged = tx.execute(include_str!("sql/merge_identities.sql"), params![target, source, self.tenant])?;
+
+        tx.commit()?;
+
+        Ok(merged > 0)
+    }
+
+    /// Splits an asset id out of its identity so that its snapshots form their own history again.
+    ///
+    /// When the asset id is the one other members are grouped under, the remaining members are
+    /// regrouped under the smallest of their asset ids. Returns `false` when the asset id is unknown.
+    pub fn split_identity(&self, asset_id: &str) -> anyhow::Result<bool> {
+        let _timer = metrics::time_query("split_identity");
+        let mut connection = self.connection()?;
+        let tx = connection.transaction()?;
+
+        tx.execute(include_str!("sql/split_identity.sql"), params![asset_id, self.tenant])?;
+        let detached = tx.execute(include_str!("sql/detach_identity.sql"), params![asset_id, self.tenant])?;
+
+        tx.commit()?;
+
+        Ok(detached > 0)
+    }
+
+    /// Assigns the identities recorded without a tenant, such as those migrated from before tenants, to a tenant.
+    ///
+    /// Returns how many identities were assigned.
+    pub fn claim_unowned(&self, tenant: &str) -> anyhow::Result<usize> {
+        let _timer = metrics::time_query("claim_unowned");
+        let connection = self.connection()?;
+
+        Ok(connection.execute(include_str!("sql/claim_unowned.sql"), params![tenant])?)
+    }
+
+    /// Stores a new API key along with the hash of its secret.
+    pub fn create_api_key(&self, key: &ApiKey, hash: &str) -> anyhow::Result<()> {
+        let _timer = metrics::time_query("create_api_key");
+        let connection = self.connection()?;
+        connection.execute(
+            include_str!("sql/insert_api_key.sql"),
+            params![key.id, key.name, hash, serde_json::to_string(&key.scopes)?, key.tenant_id, key.created_at],
+        )?;
+
+        Ok(())
+    }
+
+    /// Every API key, including revoked ones, oldest first.
+    pub fn api_keys(&self) -> anyhow::Result<Vec<ApiKey>> {
+        let _timer = metrics::time_query("api_keys");
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(include_str!("sql/api_keys.sql"))?;
+        let keys = statement.query_map([], map_row_to_api_key)?;
+
+        Ok(keys.collect::<Result<_, _>>()?)
+    }
+
+    /// The API key with the hash of a secret, unless it was revoked.
+    pub fn find_api_key(&self, hash: &str) -> anyhow::Result<Option<ApiKey>> {
+        let _timer = metrics::time_query("find_api_key");
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(include_str!("sql/find_api_key.sql"))?;
+        let mut keys = statement.query_map(params![hash], map_row_to_api_key)?;
+
+        Ok(keys.next().transpose()?)
+    }
+
+    /// Revokes an API key. Returns `false` when the key does not exist or was already revoked.
+    pub fn revoke_api_key(&self, id: &str) -> anyhow::Result<bool> {
+        let _timer = metrics::time_query("revoke_api_key");
+        let connection = self.connection()?;
+        let revoked = connection.execute(include_str!("sql/revoke_api_key.sql"), params![Utc::now(), id])?;
+
+        Ok(revoked > 0)
+    }
+}
+
+/// Registers the identity of a snapshot, supersedes the version it corrects, if any, and inserts it.
+///
+/// The identity and tenant of the snapshot are updated to the ones its asset id actually belongs to.
+/// When a tenant is given, the asset id must belong to it.
+fn insert_snapshot(
+    tx: &duckdb::Transaction,
+    asset: &mut Asset,
+    tenant: Option<&str>,
+    recorded_at: DateTime<Utc>,
+) -> anyhow::Result<Recorded> {
+    tx.execute(
+        include_str!("sql/register_identity.sql"),
+        params![asset.asset_id, asset.identity_id, tenant.or(asset.tenant_id.as_deref())],
+    )?;
+    let (identity_id, owner): (String, Option<String>) = tx.query_row(
+        include_str!("sql/identity_of.sql"),
+        params![asset.asset_id],
+        |r| Ok((r.get(0)?, r.get(1)?)),
+    )?;
+    if tenant.is_some() && owner.as_deref() != tenant {
+        bail!("Asset {} belongs to another tenant", asset.asset_id);
+    }
+    asset.identity_id = identity_id;
+    asset.tenant_id = owner;
+    let superseded = tx.execute(
+        include_str!("sql/supersede.sql"),
+        params![recorded_at, asset.asset_id, asset.balance_as_of],
+    )?;
+    tx.execute(
+        include_str!("sql/insert.sql"),
+        params![
+            asset.asset_id,
+            asset.balance_as_of,
+            asset.balance_cost_basis,
+            asset.balance_current,
+            asset.creation_date,
+            asset.deactivate_by,
+            asset.include_in_net_worth,
+            asset.is_active,
+            asset.is_asset,
+            asset.is_favorite,
+            asset.last_update,
+            asset.last_update_attempt,
+            asset.modification_date,
+            asset.nickname,
+            asset.primary_asset_category,
+            asset.wealth_asset_type,
+            asset.wid,
+            recorded_at,
+        ],
+    )?;
+
+    if superseded > 0 {
+        Ok(Recorded::Updated)
+    } else {
+        Ok(Recorded::Inserted)
+    }
 }
 
 fn map_row_to_asset(row: &duckdb::Row) -> duckdb::Result<Asset> {
     Ok(Asset {
-        asset_id: row.get(0)?,
-        balance_as_of: row.get(1)?,
-        balance_current: row.get(2)?,
-        creation_date: row.get(3)?,
-        deactivate_by: row.get(4)?,
-        include_in_net_worth: row.get(5)?,
-        is_active: row.get(6)?,
-        is_asset: row.get(7)?,
-        is_favorite: row.get(8)?,
-        last_update: row.get(9)?,
-        last_update_attempt: row.get(10)?,
-        modification_date: row.get(11)?,
-        nickname: row.get(12)?,
-        primary_asset_category: row.get(13)?,
-        wealth_asset_type: row.get(14)?,
-        wid: row.get(15)?,
+        asset_id: row.get("asset_id")?,
+        balance_as_of: row.get("balance_as_of")?,
+        balance_cost_basis: row.get("balance_cost_basis")?,
+        balance_current: row.get("balance_current")?,
+        creation_date: row.get("creation_date")?,
+        deactivate_by: row.get("deactivate_by")?,
+        include_in_net_worth: row.get("include_in_net_worth")?,
+        is_active: row.get("is_active")?,
+        is_asset: row.get("is_asset")?,
+        is_favorite: row.get("is_favorite")?,
+        last_update: row.get("last_update")?,
+        last_update_attempt: row.get("last_update_attempt")?,
+        modification_date: row.get("modification_date")?,
+        nickname: row.get("nickname")?,
+        primary_asset_category: row.get("primary_asset_category")?,
+        wealth_asset_type: row.get("wealth_asset_type")?,
+        wid: row.get("wid")?,
+        identity_id: row.get("identity_id")?,
+        tenant_id: row.get("tenant_id")?,
     })
-}
\ No newline at end of file
+}
+fn read_only_pool(path: &Path, max_connections: u32) -> anyhow::Result<r2d2::Pool<DuckdbConnectionManager>> {
+    let config = Config::default().access_mode(AccessMode::ReadOnly)?;
+    let manager = DuckdbConnectionManager::file_with_flags(path, config)?;
+
+    Ok(r2d2::Pool::builder().max_size(max_connections).build(manager)?)
+}
+
+/// Removes a database file and its write-ahead log, if they exist.
+fn remove_database_file(path: &Path) -> anyhow::Result<()> {
+    let mut wal = path.as_os_str().to_os_string();
+    wal.push(".wal");
+
+    for file in [path, Path::new(&wal)] {
+        match std::fs::remove_file(file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn map_row_to_api_key(row: &duckdb::Row) -> duckdb::Result<ApiKey> {
+    let scopes: String = row.get(2)?;
+
+    Ok(ApiKey {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        scopes: serde_json::from_str(&scopes)
+            .map_err(|e| duckdb::Error::FromSqlConversionFailure(2, duckdb::types::Type::Text, Box::new(e)))?,
+        tenant_id: row.get(3)?,
+        created_at: row.get(4)?,
+        revoked_at: row.get(5)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn repository() -> AssetRepository {
+        let repository = empty_repository();
+        repository.setup().unwrap();
+        repository
+    }
+
+    fn empty_repository() -> AssetRepository {
+        let manager = DuckdbConnectionManager::memory().unwrap();
+        let pool = r2d2::Pool::builder().max_size(1).build(manager).unwrap();
+
+        AssetRepository {
+            pool: Arc::new(RwLock::new(pool)),
+            pool_timeout: Duration::from_secs(1),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            tenant: None,
+            read_only_path: None,
+        }
+    }
+
+    /// The structure of databases created before snapshots were versioned, such as `examples/assets.db`.
+    const UNVERSIONED_STRUCTURE: &str = r#"
+        CREATE TABLE assets (
+            asset_id VARCHAR(255) PRIMARY KEY NOT NULL,
+            balance_as_of DATETIME NOT NULL,
+            balance_current DOUBLE NOT NULL,
+            creation_date DATETIME NOT NULL,
+            deactivate_by DATETIME,
+            include_in_net_worth BOOLEAN NOT NULL,
+            is_active BOOLEAN NOT NULL,
+            is_asset BOOLEAN NOT NULL,
+            is_favorite BOOLEAN NOT NULL,
+            last_update DATETIME NOT NULL,
+            last_update_attempt DATETIME NOT NULL,
+            modification_date DATETIME NOT NULL,
+            nickname VARCHAR(255) NOT NULL,
+            primary_asset_category VARCHAR(255) NOT NULL,
+            wealth_asset_type VARCHAR(255) NOT NULL,
+            wid HUGEINT NOT NULL,
+        );
+        INSERT INTO assets VALUES (
+            'a', '2025-03-28', 100.0, '2025-01-01', NULL, true, true, true, false,
+            '2025-03-28', '2025-03-28', '2025-03-28', 'Cash', '"Cash"', '"Cash"', 1
+        );
+    "#;
+
+    fn page(limit: usize) -> BalancePage {
+        BalancePage {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    fn asset(asset_id: &str, nickname: &str, balance: f64, balance_as_of: DateTime<Utc>) -> Asset {
+        Asset {
+            asset_id: asset_id.to_string(),
+            balance_as_of,
+            balance_cost_basis: None,
+            balance_current: balance,
+            creation_date: balance_as_of,
+            deactivate_by: None,
+            include_in_net_worth: true,
+            is_active: true,
+            is_asset: true,
+            is_favorite: false,
+            last_update: balance_as_of,
+            last_update_attempt: balance_as_of,
+            modification_date: balance_as_of,
+            nickname: nickname.to_string(),
+            primary_asset_category: PrimaryAssetCategory::Cash,
+            wealth_asset_type: WealthAssetType::Cash,
+            wid: 1,
+            identity_id: asset_id.to_string(),
+            tenant_id: None,
+        }
+    }
+
+    #[test]
+    fn corrections_preserve_known_history() {
+        let repository = repository();
+        let as_of = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+
+        let recorded = repository.insert(vec![asset("a", "Cash", 100.0, as_of)]).unwrap();
+        assert_eq!(vec![Recorded::Inserted], recorded);
+        // The database stores system time with microsecond precision.
+        std::thread::sleep(Duration::from_millis(1));
+        let before_correction = Utc::now();
+        std::thread::sleep(Duration::from_millis(1));
+        let recorded = repository.insert(vec![asset("a", "Cash", 150.0, as_of)]).unwrap();
+        assert_eq!(vec![Recorded::Updated], recorded);
+        let after_correction = Utc::now();
+
+        let known = repository.balances(as_of, before_correction, &BalanceFilter::default(), &[], &page(10)).unwrap();
+        assert_eq!(1, known.len());
+        assert_eq!(100.0, known[0].balance_current);
+
+        let corrected = repository
+            .balances(as_of, after_correction, &BalanceFilter::default(), &[], &page(10))
+            .unwrap();
+        assert_eq!(1, corrected.len());
+        assert_eq!(150.0, corrected[0].balance_current);
+
+        assert_eq!(1, repository.count_balances(as_of, after_correction, &BalanceFilter::default()).unwrap());
+        assert_eq!(0, repository.count_balances(as_of, as_of, &BalanceFilter::default()).unwrap());
+    }
+
+    #[test]
+    fn setup_migrates_unversioned_databases() {
+        let repository = empty_repository();
+        repository.connection().unwrap().execute_batch(UNVERSIONED_STRUCTURE).unwrap();
+        assert_eq!(None, repository.schema_version().unwrap());
+        repository.setup().unwrap();
+        assert_eq!(Some(SCHEMA_VERSION), repository.schema_version().unwrap());
+        // Setting up a migrated database again leaves it as is.
+        repository.setup().unwrap();
+
+        // Snapshots of unversioned databases are their own identity.
+        let as_of = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+        let found = repository.find_asset("a", as_of, Utc::now()).unwrap().unwrap();
+        assert_eq!(("a", 100.0, None), (found.identity_id.as_str(), found.balance_current, found.balance_cost_basis));
+
+        let recorded = repository.insert(vec![asset("a", "Cash", 150.0, as_of)]).unwrap();
+        assert_eq!(vec![Recorded::Updated], recorded);
+
+        // They are known from when they were last modified.
+        let known = repository
+            .balances(as_of, as_of, &BalanceFilter::default(), &[], &page(10))
+            .unwrap();
+        assert_eq!(1, known.len());
+        assert_eq!(100.0, known[0].balance_current);
+    }
+
+    #[test]
+    fn renames_keep_identity() {
+        let repository = repository();
+        let first = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+        let second = Utc.with_ymd_and_hms(2025, 4, 28, 0, 0, 0).unwrap();
+
+        repository
+            .insert(vec![
+                asset("a", "Checking", 100.0, first),
+                asset("b", "Checking", 200.0, first),
+            ])
+            .unwrap();
+        repository.insert(vec![asset("a", "Everyday", 150.0, second)]).unwrap();
+
+        let balances = repository.balances(second, Utc::now(), &BalanceFilter::default(), &[], &page(10)).unwrap();
+        assert_eq!(2, balances.len());
+        assert_eq!(("b", "Checking"), (balances[0].identity_id.as_str(), balances[0].nickname.as_str()));
+        assert_eq!(("a", "Everyday"), (balances[1].identity_id.as_str(), balances[1].nickname.as_str()));
+
+        let history = repository.nickname_history("a").unwrap();
+        let nicknames: Vec<_> = history.iter().map(|c| c.nickname.as_str()).collect();
+        assert_eq!(vec!["Checking", "Everyday"], nicknames);
+    }
+
+    #[test]
+    fn merge_and_split_identities() {
+        let repository = repository();
+        let first = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+        let second = Utc.with_ymd_and_hms(2025, 4, 28, 0, 0, 0).unwrap();
+
+        repository
+            .insert(vec![
+                asset("a", "Old Bank", 100.0, first),
+                asset("b", "New Bank", 150.0, second),
+                asset("c", "Other", 10.0, second),
+            ])
+            .unwrap();
+
+        assert!(repository.merge_identities("a", "b").unwrap());
+        assert!(!repository.merge_identities("missing", "c").unwrap());
+        assert_eq!(2, repository.count_balances(second, Utc::now(), &BalanceFilter::default()).unwrap());
+
+        let balances = repository.balances(second, Utc::now(), &BalanceFilter::default(), &[], &page(10)).unwrap();
+        assert_eq!(("a", 150.0), (balances[0].identity_id.as_str(), balances[0].balance_current));
+
+        // Splitting the identity root regroups the remaining members under a new root.
+        assert!(repository.split_identity("a").unwrap());
+        assert!(!repository.split_identity("missing").unwrap());
+        assert_eq!(3, repository.count_balances(second, Utc::now(), &BalanceFilter::default()).unwrap());
+        assert_eq!(1, repository.nickname_history("b").unwrap().len());
+    }
+
+    #[test]
+    fn merged_snapshots_on_the_same_date_prefer_the_latest_recorded() {
+        let repository = repository();
+        let as_of = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+        repository.insert(vec![asset("b", "New Bank", 150.0, as_of)]).unwrap();
+        repository.insert(vec![asset("a", "Old Bank", 100.0, as_of)]).unwrap();
+        assert!(repository.merge_identities("b", "a").unwrap());
+
+        for _ in 0..5 {
+            let balances = repository.balances(as_of, Utc::now(), &BalanceFilter::default(), &[], &page(10)).unwrap();
+            assert_eq!(("a", 100.0), (balances[0].asset_id.as_str(), balances[0].balance_current));
+        }
+    }
+
+    #[test]
+    fn filters_apply_to_latest_snapshot() {
+        let repository = repository();
+        let as_of = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+        let mut house = asset("b", "Beach House", 500000.0, as_of);
+        house.primary_asset_category = PrimaryAssetCategory::RealEstate;
+        house.is_favorite = true;
+        let mut art = asset("c", "Art", 20000.0, as_of);
+        art.primary_asset_category = PrimaryAssetCategory::Unknown("Collectibles".to_string());
+
+        repository
+            .insert(vec![asset("a", "Checking", 1000.0, as_of), house, art])
+            .unwrap();
+
+        let count = |filter: BalanceFilter| repository.count_balances(as_of, Utc::now(), &filter).unwrap();
+
+        assert_eq!(
+            2,
+            count(BalanceFilter {
+                categories: Some(vec![
+                    PrimaryAssetCategory::RealEstate,
+                    PrimaryAssetCategory::Unknown(String::new())
+                ]),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            0,
+            count(BalanceFilter {
+                categories: Some(vec![]),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            1,
+            count(BalanceFilter {
+                is_favorite: Some(true),
+                min_balance: Some(1000.0),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            2,
+            count(BalanceFilter {
+                min_balance: Some(1000.0),
+                max_balance: Some(20000.0),
+                ..Default::default()
+            })
+        );
+
+        let filter = BalanceFilter {
+            nickname: Some("HOUSE".to_string()),
+            ..Default::default()
+        };
+        let balances = repository
+            .balances(as_of, Utc::now(), &filter, &[], &page(10))
+            .unwrap();
+        assert_eq!(1, balances.len());
+        assert_eq!("Beach House", balances[0].nickname);
+    }
+
+    #[test]
+    fn unreadable_rows_are_reported() {
+        let repository = repository();
+        let as_of = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+        repository
+            .insert(vec![asset("a", "Checking", 1000.0, as_of), asset("b", "Savings", 500.0, as_of)])
+            .unwrap();
+        repository
+            .connection()
+            .unwrap()
+            .execute("UPDATE assets SET primary_asset_category = 'not json' WHERE asset_id = 'b'", [])
+            .unwrap();
+
+        assert!(repository.balances(as_of, Utc::now(), &BalanceFilter::default(), &[], &page(10)).is_err());
+        assert!(repository.snapshots("b", None, None, Utc::now(), None, 10).is_err());
+    }
+
+    #[test]
+    fn sorts_by_requested_order() {
+        let repository = repository();
+        let as_of = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+        let mut savings = asset("c", "Savings", 100.0, as_of);
+        savings.is_favorite = true;
+
+        repository
+            .insert(vec![
+                asset("a", "Checking", 300.0, as_of),
+                asset("b", "Brokerage", 200.0, as_of),
+                savings,
+            ])
+            .unwrap();
+
+        let nicknames = |order: &[BalanceOrder]| {
+            repository
+                .balances(as_of, Utc::now(), &BalanceFilter::default(), order, &page(10))
+                .unwrap()
+                .into_iter()
+                .map(|a| a.nickname)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec!["Brokerage", "Checking", "Savings"], nicknames(&[]));
+        assert_eq!(
+            vec!["Savings", "Checking", "Brokerage"],
+            nicknames(&[
+                BalanceOrder::ascending(BalanceSortField::FavoriteFirst),
+                BalanceOrder {
+                    field: BalanceSortField::Balance,
+                    descending: true,
+                },
+            ])
+        );
+        assert_eq!(
+            vec!["Brokerage", "Checking", "Savings"],
+            nicknames(&[BalanceOrder {
+                field: BalanceSortField::FavoriteFirst,
+                descending: true,
+            }])
+        );
+    }
+
+    #[test]
+    fn keyset_pages_cover_every_asset_once() {
+        let repository = repository();
+        let as_of = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+        let balances = [300.0, 100.0, 300.0, 200.0, 100.0];
+
+        repository
+            .insert(
+                balances
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &balance)| asset(&format!("{i}"), "Account", balance, as_of))
+                    .collect(),
+            )
+            .unwrap();
+
+        let order = [BalanceOrder {
+            field: BalanceSortField::Balance,
+            descending: true,
+        }];
+        let fetch = |page: BalancePage| {
+            repository
+                .balances(as_of, Utc::now(), &BalanceFilter::default(), &order, &page)
+                .unwrap()
+        };
+
+        let mut seen = Vec::new();
+        let mut after = None;
+        loop {
+            let assets = fetch(BalancePage {
+                after: after.take(),
+                limit: 2,
+                ..Default::default()
+            });
+            if assets.is_empty() {
+                break;
+            }
+            after = assets.last().map(|a| key_of(&order, a));
+            seen.extend(assets.into_iter().map(|a| a.identity_id));
+        }
+        assert_eq!(vec!["0", "2", "3", "1", "4"], seen);
+
+        let last = fetch(BalancePage {
+            limit: 2,
+            from_end: true,
+            ..Default::default()
+        });
+        let ids: Vec<_> = last.iter().map(|a| a.identity_id.as_str()).collect();
+        assert_eq!(vec!["1", "4"], ids);
+
+        let before = fetch(BalancePage {
+            before: Some(key_of(&order, &last[0])),
+            limit: 2,
+            from_end: true,
+            ..Default::default()
+        });
+        let ids: Vec<_> = before.iter().map(|a| a.identity_id.as_str()).collect();
+        assert_eq!(vec!["2", "3"], ids);
+    }
+
+    #[test]
+    fn finds_asset_and_pages_its_history() {
+        let repository = repository();
+        let dates: Vec<_> = (1..=3)
+            .map(|month| Utc.with_ymd_and_hms(2025, month, 1, 0, 0, 0).unwrap())
+            .collect();
+        // Every account of a user shares their wid.
+        let other = asset("b", "Savings", 50.0, dates[0]);
+
+        repository
+            .insert(
+                dates
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &date)| asset("a", "Checking", 100.0 * (i + 1) as f64, date))
+                    .chain(std::iter::once(other))
+                    .collect(),
+            )
+            .unwrap();
+
+        let now = Utc::now();
+        let found = repository.find_asset("a", dates[1], now).unwrap().unwrap();
+        assert_eq!(200.0, found.balance_current);
+        let found = repository.find_assets_by_wid(1, dates[2], now).unwrap();
+        assert_eq!(vec!["a", "b"], found.iter().map(|a| a.identity_id.as_str()).collect::<Vec<_>>());
+        assert!(repository.find_assets_by_wid(2, dates[2], now).unwrap().is_empty());
+        assert!(repository.find_asset("c", now, now).unwrap().is_none());
+
+        let newest = repository.snapshots("a", None, None, now, None, 2).unwrap();
+        let balances: Vec<_> = newest.iter().map(|a| a.balance_current).collect();
+        assert_eq!(vec![300.0, 200.0], balances);
+
+        let after = SnapshotKey::of(&newest[1]);
+        let rest = repository.snapshots("a", None, None, now, Some(&after), 2).unwrap();
+        let balances: Vec<_> = rest.iter().map(|a| a.balance_current).collect();
+        assert_eq!(vec![100.0], balances);
+
+        let snapshot = repository.find_snapshot("a", dates[0], now).unwrap().unwrap();
+        assert_eq!(100.0, snapshot.balance_current);
+        assert!(repository.find_snapshot("b", dates[1], now).unwrap().is_none());
+
+        let ranged = repository
+            .snapshots("a", Some(dates[1]), Some(dates[1]), now, None, 10)
+            .unwrap();
+        assert_eq!(1, ranged.len());
+        assert_eq!(200.0, ranged[0].balance_current);
+    }
+
+    #[test]
+    fn audited_changes_are_recorded_with_their_snapshot() {
+        let repository = repository();
+        let as_of = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+        let renamed_at = Utc.with_ymd_and_hms(2025, 4, 1, 0, 0, 0).unwrap();
+
+        repository.insert(vec![asset("a", "Cash", 100.0, as_of)]).unwrap();
+        repository
+            .insert_audited(asset("a", "Savings", 100.0, renamed_at), "rename", r#"{"nickname":"Savings"}"#)
+            .unwrap();
+
+        let trail = repository.audit_trail("a").unwrap();
+        assert_eq!(1, trail.len());
+        assert_eq!("rename", trail[0].action);
+        assert_eq!(renamed_at, trail[0].balance_as_of);
+
+        let snapshot = repository.find_snapshot("a", renamed_at, Utc::now()).unwrap().unwrap();
+        assert_eq!("Savings", snapshot.nickname);
+    }
+
+    #[test]
+    fn commits_are_published_to_subscribers() {
+        let repository = repository();
+        let as_of = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+
+        let later = Utc.with_ymd_and_hms(2025, 4, 28, 0, 0, 0).unwrap();
+
+        repository
+            .insert(vec![
+                asset("a", "Cash", 100.0, as_of),
+                asset("b", "Savings", 50.0, as_of),
+            ])
+            .unwrap();
+        assert!(repository.merge_identities("a", "b").unwrap());
+
+        let mut events = repository.subscribe();
+        repository
+            .insert(vec![
+                asset("a", "Cash", 150.0, as_of),
+                asset("b", "Savings", 60.0, later),
+            ])
+            .unwrap();
+
+        let committed = events.try_recv().unwrap();
+        assert_eq!(Origin::Import, committed.origin);
+        assert_eq!((1, 1), (committed.inserted(), committed.updated()));
+        let identities: Vec<_> = committed.snapshots.iter().map(|a| a.identity_id.as_str()).collect();
+        assert_eq!(vec!["a", "a"], identities);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn tenants_only_see_their_own_assets() {
+        let repository = repository();
+        let alice = repository.scoped(Some("alice".to_string()));
+        let bob = repository.scoped(Some("bob".to_string()));
+        let as_of = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+
+        alice.insert(vec![asset("a", "Alice's Bank", 100.0, as_of)]).unwrap();
+        bob.insert(vec![asset("b", "Bob's Bank", 50.0, as_of)]).unwrap();
+
+        let count = |repository: &AssetRepository| {
+            repository
+                .count_balances(as_of, Utc::now(), &BalanceFilter::default())
+                .unwrap()
+        };
+        assert_eq!((1, 1, 2), (count(&alice), count(&bob), count(&repository)));
+        assert!(bob.find_asset("a", as_of, Utc::now()).unwrap().is_none());
+        assert_eq!(50.0, bob.net_worth(as_of, Utc::now()).unwrap().totals.net_worth());
+
+        // Asset ids belong to the tenant that recorded them first.
+        assert!(bob.insert(vec![asset("a", "Not Bob's", 1.0, as_of)]).is_err());
+        assert!(!bob.merge_identities("b", "a").unwrap());
+        assert_eq!(100.0, alice.find_asset("a", as_of, Utc::now()).unwrap().unwrap().balance_current);
+    }
+
+    #[test]
+    fn net_worth_subtracts_liabilities() {
+        let repository = repository();
+        let as_of = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+        let mut loan = asset("b", "Loan", 300.0, as_of);
+        loan.is_asset = false;
+        let mut car = asset("c", "Car", 5000.0, as_of);
+        car.include_in_net_worth = false;
+        car.primary_asset_category = PrimaryAssetCategory::OtherProperty;
+        car.wealth_asset_type = WealthAssetType::Vehicle;
+
+        repository
+            .insert(vec![asset("a", "Cash", 1000.0, as_of), loan, car])
+            .unwrap();
+
+        let net_worth = repository.net_worth(as_of, Utc::now()).unwrap();
+        assert_eq!(1000.0, net_worth.totals.assets);
+        assert_eq!(300.0, net_worth.totals.liabilities);
+        assert_eq!(700.0, net_worth.totals.net_worth());
+        assert_eq!(1, net_worth.by_category.len());
+        assert_eq!("Cash", net_worth.by_category[0].0.name());
+        assert_eq!(1, net_worth.by_type.len());
+        assert_eq!(700.0, net_worth.by_type[0].1.net_worth());
+    }
+
+    #[test]
+    fn performance_between_dates() {
+        let repository = repository();
+        let from = Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2025, 4, 1, 0, 0, 0).unwrap();
+        let mut brokerage = asset("a", "Brokerage", 1100.0, to);
+        brokerage.balance_cost_basis = Some(800.0);
+
+        repository
+            .insert(vec![
+                asset("a", "Brokerage", 1000.0, from),
+                brokerage,
+                asset("b", "New Savings", 500.0, to),
+            ])
+            .unwrap();
+
+        let performance = repository.performance(from, to, Utc::now()).unwrap();
+        assert_eq!(600.0, performance.change());
+        assert_eq!(Some(60.0), performance.change_percent());
+
+        let brokerage = &performance.assets[0];
+        assert_eq!(100.0, brokerage.change());
+        assert_eq!(Some(10.0), brokerage.change_percent());
+        assert_eq!(Some(300.0), brokerage.unrealized_gain());
+        assert_eq!(Some(37.5), brokerage.unrealized_gain_percent());
+
+        let savings = &performance.assets[1];
+        assert_eq!(500.0, savings.change());
+        assert_eq!(None, savings.change_percent());
+        assert_eq!(None, savings.unrealized_gain());
+    }
+
+    #[test]
+    fn history_carries_balances_forward() {
+        let repository = repository();
+        let from = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2025, 4, 15, 0, 0, 0).unwrap();
+
+        repository
+            .insert(vec![
+                asset("a", "Cash", 100.0, Utc.with_ymd_and_hms(2025, 2, 10, 0, 0, 0).unwrap()),
+                asset("a", "Cash", 250.0, Utc.with_ymd_and_hms(2025, 4, 1, 0, 0, 0).unwrap()),
+                asset("b", "Savings", 50.0, Utc.with_ymd_and_hms(2025, 1, 5, 0, 0, 0).unwrap()),
+            ])
+            .unwrap();
+
+        let balances = repository
+            .balance_history("a", from, to, Interval::Month, Utc::now())
+            .unwrap()
+            .unwrap();
+        let balances: Vec<_> = balances.iter().map(|b| b.balance).collect();
+        assert_eq!(vec![None, Some(100.0), Some(100.0), Some(250.0)], balances);
+        assert!(repository
+            .balance_history("missing", from, to, Interval::Month, Utc::now())
+            .unwrap()
+            .is_none());
+
+        let net_worth = repository
+            .net_worth_history(from, to, Interval::Month, Utc::now())
+            .unwrap();
+        let net_worth: Vec<_> = net_worth.iter().map(|b| b.totals.net_worth()).collect();
+        assert_eq!(vec![50.0, 150.0, 150.0, 300.0], net_worth);
+    }
+
+    #[test]
+    fn unowned_identities_are_claimed_by_a_tenant() {
+        let repository = empty_repository();
+        repository.connection().unwrap().execute_batch(UNVERSIONED_STRUCTURE).unwrap();
+        repository.setup().unwrap();
+        let alice = repository.scoped(Some("alice".to_string()));
+        let as_of = Utc.with_ymd_and_hms(2025, 3, 28, 0, 0, 0).unwrap();
+
+        // Migrated identities have no tenant, so no tenant sees or changes them.
+        assert!(alice.find_asset("a", as_of, Utc::now()).unwrap().is_none());
+        assert!(alice.insert(vec![asset("a", "Cash", 150.0, as_of)]).is_err());
+
+        assert_eq!(1, repository.claim_unowned("alice").unwrap());
+        assert_eq!(0, repository.claim_unowned("bob").unwrap());
+        assert_eq!(100.0, alice.find_asset("a", as_of, Utc::now()).unwrap().unwrap().balance_current);
+        assert_eq!(vec![Recorded::Updated], alice.insert(vec![asset("a", "Cash", 150.0, as_of)]).unwrap());
+    }
+
+    #[test]
+    fn api_keys_are_found_by_hash_until_revoked() {
+        let repository = repository();
+        let key = ApiKey {
+            id: "key".to_string(),
+            name: "ingestion".to_string(),
+            scopes: vec![Scope::ImportWrite],
+            tenant_id: Some("tenant".to_string()),
+            created_at: Utc::now(),
+            revoked_at: None,
+        };
+        repository.create_api_key(&key, "hash").unwrap();
+
+        let found = repository.find_api_key("hash").unwrap().unwrap();
+        assert_eq!(vec![Scope::ImportWrite], found.scopes);
+        assert_eq!(Some("tenant".to_string()), found.tenant_id);
+        assert!(repository.find_api_key("other").unwrap().is_none());
+
+        assert!(repository.revoke_api_key("key").unwrap());
+        assert!(!repository.revoke_api_key("key").unwrap());
+        assert!(repository.find_api_key("hash").unwrap().is_none());
+        assert!(repository.api_keys().unwrap()[0].revoked_at.is_some());
+    }
+
+    #[test]
+    fn read_only_copies_are_reloaded_when_replaced() {
+        let directory = std::env::temp_dir().join(format!("assets-{}-read-only", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("published.db");
+
+        let repository = repository();
+        let now = Utc::now();
+        repository.insert(vec![asset("a", "Cash", 100.0, now)]).unwrap();
+        repository.copy_to(&path).unwrap();
+
+        let read_only = AssetRepository::open_read_only(&path, 2, Duration::from_secs(1)).unwrap();
+        assert_eq!(100.0, read_only.net_worth(Utc::now(), Utc::now()).unwrap().totals.net_worth());
+        assert!(read_only.insert(vec![asset("b", "Cash", 1.0, now)]).is_err());
+
+        // Connections taken before the reload keep reading the old file.
+        let old = read_only.connection().unwrap();
+        repository.insert(vec![asset("b", "Cash", 50.0, now)]).unwrap();
+        repository.copy_to(&path).unwrap();
+        read_only.reload().unwrap();
+
+        assert_eq!(150.0, read_only.net_worth(Utc::now(), Utc::now()).unwrap().totals.net_worth());
+        let count: i64 = old.query_row("SELECT count(*) FROM assets", [], |row| row.get(0)).unwrap();
+        assert_eq!(1, count);
+        assert!(repository.reload().is_err());
+
+        drop(old);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn setup_records_the_schema_version() {
+        let repository = repository();
+        assert_eq!(Some(SCHEMA_VERSION), repository.schema_version().unwrap());
+        assert!(repository.ping(Duration::from_millis(100)).is_ok());
+        assert_eq!(None, repository.last_recorded_at().unwrap());
+
+        // Setting up again keeps a single version.
+        repository.setup().unwrap();
+        assert_eq!(Some(SCHEMA_VERSION), repository.schema_version().unwrap());
+
+        // Databases set up by a newer version are left as they are.
+        let newer = SCHEMA_VERSION + 1;
+        repository.connection().unwrap().execute("UPDATE schema_version SET version = ?", params![newer]).unwrap();
+        assert!(repository.setup().is_err());
+        assert_eq!(Some(newer), repository.schema_version().unwrap());
+
+        let before = Utc::now();
+        repository.insert(vec![asset("a", "Cash", 1.0, before)]).unwrap();
+        let recorded_at = repository.last_recorded_at().unwrap().unwrap();
+        assert!(recorded_at.timestamp_micros() >= before.timestamp_micros());
+    }
+}
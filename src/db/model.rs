@@ -1,14 +1,16 @@
-use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use chrono::{DateTime, Datelike, Days, NaiveDate, Utc};
 use duckdb::ToSql;
 use duckdb::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
 use serde::{Deserialize, Serialize};
 
 // Models an asset entry imported via the Wealth Import API.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Asset {
     pub asset_id: String,
     pub balance_as_of: DateTime<Utc>,
+    pub balance_cost_basis: Option<f64>,
     pub balance_current: f64,
     pub creation_date: DateTime<Utc>,
     pub deactivate_by: Option<DateTime<Utc>>,
@@ -23,9 +25,308 @@ pub struct Asset {
     pub primary_asset_category: PrimaryAssetCategory,
     pub wealth_asset_type: WealthAssetType,
     pub wid: i128,
+    /// The stable identity this snapshot's history is grouped under.
+    pub identity_id: String,
+    /// The tenant the asset id belongs to. Used when recording the asset id for the first time
+    /// through a repository that is not scoped to a tenant.
+    pub tenant_id: Option<String>,
+}
+
+/// A nickname an asset identity was known by, starting from the balance date it was first used.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NicknameChange {
+    pub nickname: String,
+    pub since: DateTime<Utc>,
+}
+
+/// Whether recording a snapshot added a new one or corrected one that was already known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recorded {
+    Inserted,
+    Updated,
+}
+
+/// Where a batch of committed snapshots came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    Import,
+    /// A change made outside of imports, which is recorded in the audit trail.
+    Change,
+}
+
+/// A batch of snapshots committed in a single transaction, published to subscribers after the commit.
+#[derive(Debug, Clone)]
+pub struct Committed {
+    pub recorded_at: DateTime<Utc>,
+    pub origin: Origin,
+    pub snapshots: Arc<Vec<Asset>>,
+    /// Whether each snapshot was inserted or updated, in the same order as the snapshots.
+    pub recorded: Arc<Vec<Recorded>>,
+}
+
+impl Committed {
+    /// The number of snapshots that were not known before.
+    pub fn inserted(&self) -> usize {
+        self.recorded.iter().filter(|r| **r == Recorded::Inserted).count()
+    }
+
+    /// The number of snapshots that corrected a known one.
+    pub fn updated(&self) -> usize {
+        self.recorded.iter().filter(|r| **r == Recorded::Updated).count()
+    }
+
+    /// The part of the batch a tenant may see, if any. Subscribers that are not scoped to a tenant see whole batches.
+    pub fn visible_to(self, tenant: Option<&str>) -> Option<Self> {
+        let Some(tenant) = tenant else {
+            return Some(self);
+        };
+
+        let (snapshots, recorded): (Vec<_>, Vec<_>) = self
+            .snapshots
+            .iter()
+            .zip(self.recorded.iter())
+            .filter(|(snapshot, _)| snapshot.tenant_id.as_deref() == Some(tenant))
+            .map(|(snapshot, recorded)| (snapshot.clone(), *recorded))
+            .unzip();
+
+        (!snapshots.is_empty()).then(|| Self {
+            snapshots: Arc::new(snapshots),
+            recorded: Arc::new(recorded),
+            ..self
+        })
+    }
+}
+
+/// A change made to an asset outside of imports.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    /// The system time the change was recorded at, shared with the snapshot it produced.
+    pub recorded_at: DateTime<Utc>,
+    pub action: String,
+    pub asset_id: String,
+    pub balance_as_of: DateTime<Utc>,
+    /// The changed attributes, as JSON.
+    pub details: String,
+}
+
+/// A permission granted to an API key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    /// Import assets and change them.
+    #[serde(rename = "import:write")]
+    ImportWrite,
+    /// Query assets and subscribe to their updates.
+    #[serde(rename = "assets:read")]
+    AssetsRead,
+    /// Everything, including managing API keys.
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    /// The name of the scope as used by clients.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scope::ImportWrite => "import:write",
+            Scope::AssetsRead => "assets:read",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Scope::ImportWrite, Scope::AssetsRead, Scope::Admin]
+            .into_iter()
+            .find(|scope| scope.name() == s)
+            .ok_or_else(|| anyhow::anyhow!("Unknown scope {s}, expected one of import:write, assets:read or admin"))
+    }
+}
+
+/// A key machine clients authenticate with. Only a hash of the secret key is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// The tenant the key is limited to. Keys without a tenant see the data of every tenant.
+    pub tenant_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// The sums of assets and liabilities that are included in net worth.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetWorthTotals {
+    pub assets: f64,
+    pub liabilities: f64,
+}
+
+impl NetWorthTotals {
+    /// Assets minus liabilities.
+    pub fn net_worth(&self) -> f64 {
+        self.assets - self.liabilities
+    }
+}
+
+/// Net worth as of a point in time, broken down by category and type.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetWorth {
+    pub totals: NetWorthTotals,
+    pub by_category: Vec<(PrimaryAssetCategory, NetWorthTotals)>,
+    pub by_type: Vec<(WealthAssetType, NetWorthTotals)>,
+}
+
+/// The change in balance of an asset between two dates.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetPerformance {
+    pub identity_id: String,
+    pub nickname: String,
+    /// Empty when the asset had no snapshot at the starting date.
+    pub starting_balance: Option<f64>,
+    pub ending_balance: f64,
+    pub balance_cost_basis: Option<f64>,
+}
+
+impl AssetPerformance {
+    /// The absolute change in balance. Assets without a starting balance are treated as starting from zero.
+    pub fn change(&self) -> f64 {
+        self.ending_balance - self.starting_balance.unwrap_or_default()
+    }
+
+    /// The change in balance in percent of the starting balance.
+    pub fn change_percent(&self) -> Option<f64> {
+        percent_change(self.starting_balance?, self.ending_balance)
+    }
+
+    /// The ending balance minus the cost basis.
+    pub fn unrealized_gain(&self) -> Option<f64> {
+        Some(self.ending_balance - self.balance_cost_basis?)
+    }
+
+    /// The unrealized gain in percent of the cost basis.
+    pub fn unrealized_gain_percent(&self) -> Option<f64> {
+        percent_change(self.balance_cost_basis?, self.ending_balance)
+    }
 }
 
+/// The change in net worth and in the balance of every asset between two dates.
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Performance {
+    pub starting: NetWorthTotals,
+    pub ending: NetWorthTotals,
+    pub assets: Vec<AssetPerformance>,
+}
+
+impl Performance {
+    /// The absolute change in net worth.
+    pub fn change(&self) -> f64 {
+        self.ending.net_worth() - self.starting.net_worth()
+    }
+
+    /// The change in net worth in percent of the starting net worth.
+    pub fn change_percent(&self) -> Option<f64> {
+        percent_change(self.starting.net_worth(), self.ending.net_worth())
+    }
+}
+
+/// The change from one value to another in percent. Empty when starting from zero.
+pub fn percent_change(from: f64, to: f64) -> Option<f64> {
+    if from == 0.0 {
+        None
+    } else {
+        Some((to - from) / from.abs() * 100.0)
+    }
+}
+
+/// The width of the buckets in a time series.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Interval {
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Interval {
+    /// The date part that bucket starts are truncated to.
+    pub fn date_part(&self) -> &'static str {
+        match self {
+            Interval::Day => "day",
+            Interval::Week => "week",
+            Interval::Month => "month",
+            Interval::Quarter => "quarter",
+            Interval::Year => "year",
+        }
+    }
+
+    /// The distance between the starts of consecutive buckets.
+    pub fn step(&self) -> &'static str {
+        match self {
+            Interval::Day => "1 day",
+            Interval::Week => "1 week",
+            Interval::Month => "1 month",
+            Interval::Quarter => "3 months",
+            Interval::Year => "1 year",
+        }
+    }
+
+    /// The number of buckets covering a date range, counting the partial buckets at either end.
+    pub fn bucket_count(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> usize {
+        let (from, to) = (from.date_naive(), to.date_naive());
+        let months = |date: NaiveDate| i64::from(date.year()) * 12 + i64::from(date.month0());
+        let count = match self {
+            Interval::Day => (to - from).num_days(),
+            Interval::Week => {
+                let monday = from - Days::new(u64::from(from.weekday().num_days_from_monday()));
+                (to - monday).num_days() / 7
+            }
+            Interval::Month => months(to) - months(from),
+            Interval::Quarter => months(to) / 3 - months(from) / 3,
+            Interval::Year => i64::from(to.year() - from.year()),
+        };
+
+        usize::try_from(count + 1).unwrap_or(0)
+    }
+}
+
+/// The balance of an asset at the close of a time series bucket.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceBucket {
+    pub starts_at: DateTime<Utc>,
+    pub closes_at: DateTime<Utc>,
+    /// The last known balance, carried forward from earlier buckets. Empty before the first snapshot.
+    pub balance: Option<f64>,
+    pub balance_as_of: Option<DateTime<Utc>>,
+}
+
+/// The net worth at the close of a time series bucket.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetWorthBucket {
+    pub starts_at: DateTime<Utc>,
+    pub closes_at: DateTime<Utc>,
+    pub totals: NetWorthTotals,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PrimaryAssetCategory {
     Cash,
     Investment,
@@ -34,6 +335,19 @@ pub enum PrimaryAssetCategory {
     Unknown(String)
 }
 
+impl PrimaryAssetCategory {
+    /// The name of the category as provided on import.
+    pub fn name(&self) -> &str {
+        match self {
+            PrimaryAssetCategory::Cash => "Cash",
+            PrimaryAssetCategory::Investment => "Investment",
+            PrimaryAssetCategory::RealEstate => "RealEstate",
+            PrimaryAssetCategory::OtherProperty => "OtherProperty",
+            PrimaryAssetCategory::Unknown(name) => name,
+        }
+    }
+}
+
 impl ToSql for PrimaryAssetCategory {
     fn to_sql(&self) -> duckdb::Result<ToSqlOutput<'_>> {
         match serde_json::to_string(self) {
@@ -52,7 +366,7 @@ impl FromSql for PrimaryAssetCategory {
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum WealthAssetType {
     Brokerage,
     Cash,
@@ -62,6 +376,20 @@ pub enum WealthAssetType {
     Unknown(String)
 }
 
+impl WealthAssetType {
+    /// The name of the type as provided on import.
+    pub fn name(&self) -> &str {
+        match self {
+            WealthAssetType::Brokerage => "Brokerage",
+            WealthAssetType::Cash => "Cash",
+            WealthAssetType::Cryptocurrency => "Cryptocurrency",
+            WealthAssetType::RealEstate => "RealEstate",
+            WealthAssetType::Vehicle => "Vehicle",
+            WealthAssetType::Unknown(name) => name,
+        }
+    }
+}
+
 impl ToSql for WealthAssetType {
     fn to_sql(&self) -> duckdb::Result<ToSqlOutput<'_>> {
         match serde_json::to_string(self) {
@@ -0,0 +1,209 @@
+use chrono::{DateTime, Utc};
+use duckdb::ToSql;
+use duckdb::types::ToSqlOutput;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::Asset;
+
+/// A column that balance queries can be sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalanceSortField {
+    Nickname,
+    Balance,
+    BalanceAsOf,
+    Category,
+    /// Sorts favorites before other assets in ascending order.
+    FavoriteFirst,
+}
+
+/// One level of the sort order of balance queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BalanceOrder {
+    pub field: BalanceSortField,
+    pub descending: bool,
+}
+
+impl BalanceOrder {
+    pub fn ascending(field: BalanceSortField) -> Self {
+        Self {
+            field,
+            descending: false,
+        }
+    }
+
+    /// The column of `latest_snapshots` to sort by.
+    pub fn column(&self) -> &'static str {
+        match self.field {
+            BalanceSortField::Nickname => "nickname",
+            BalanceSortField::Balance => "balance_current",
+            BalanceSortField::BalanceAsOf => "balance_as_of",
+            BalanceSortField::Category => "primary_asset_category",
+            BalanceSortField::FavoriteFirst => "is_favorite",
+        }
+    }
+
+    /// Whether the column is sorted in descending order.
+    pub fn is_descending(&self) -> bool {
+        // Favorites have a true flag, so they come first when the flag is sorted descending.
+        match self.field {
+            BalanceSortField::FavoriteFirst => !self.descending,
+            _ => self.descending,
+        }
+    }
+
+    /// The value of the sort column for the given asset.
+    pub fn value(&self, asset: &Asset) -> SortValue {
+        match self.field {
+            BalanceSortField::Nickname => SortValue::Text(asset.nickname.clone()),
+            BalanceSortField::Balance => SortValue::Number(asset.balance_current),
+            BalanceSortField::BalanceAsOf => SortValue::Timestamp(asset.balance_as_of),
+            // Categories are compared in their stored representation.
+            BalanceSortField::Category => SortValue::Text(
+                serde_json::to_string(&asset.primary_asset_category).unwrap_or_default(),
+            ),
+            BalanceSortField::FavoriteFirst => SortValue::Flag(asset.is_favorite),
+        }
+    }
+}
+
+/// The value of a sort column for a single asset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SortValue {
+    Text(String),
+    Number(f64),
+    Timestamp(DateTime<Utc>),
+    Flag(bool),
+}
+
+impl ToSql for SortValue {
+    fn to_sql(&self) -> duckdb::Result<ToSqlOutput<'_>> {
+        match self {
+            SortValue::Text(value) => value.to_sql(),
+            SortValue::Number(value) => value.to_sql(),
+            SortValue::Timestamp(value) => value.to_sql(),
+            SortValue::Flag(value) => value.to_sql(),
+        }
+    }
+}
+
+/// The position of an asset within a sort order, used to page through balances with keyset predicates.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BalanceKey {
+    pub values: Vec<SortValue>,
+    pub identity_id: String,
+}
+
+/// The position of a snapshot within the history of an identity, which is sorted newest first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotKey {
+    pub balance_as_of: DateTime<Utc>,
+    pub asset_id: String,
+}
+
+impl SnapshotKey {
+    /// The key of the given snapshot.
+    pub fn of(asset: &Asset) -> Self {
+        Self {
+            balance_as_of: asset.balance_as_of,
+            asset_id: asset.asset_id.clone(),
+        }
+    }
+}
+
+/// A page of balances, bounded by the keys of the assets before and after it.
+#[derive(Debug, Default)]
+pub struct BalancePage {
+    /// Only assets that sort after this key.
+    pub after: Option<BalanceKey>,
+    /// Only assets that sort before this key.
+    pub before: Option<BalanceKey>,
+    pub limit: usize,
+    /// Take the page from the end of the range instead of the start.
+    pub from_end: bool,
+}
+
+/// The sort order including the tie-breaker on nickname. Identity is the final tie-breaker, which makes the order total.
+pub fn effective_order(orders: &[BalanceOrder]) -> Vec<BalanceOrder> {
+    let mut orders = orders.to_vec();
+    if !orders.iter().any(|o| o.field == BalanceSortField::Nickname) {
+        orders.push(BalanceOrder::ascending(BalanceSortField::Nickname));
+    }
+    orders
+}
+
+/// The key of the given asset within the sort order.
+pub fn key_of(orders: &[BalanceOrder], asset: &Asset) -> BalanceKey {
+    BalanceKey {
+        values: effective_order(orders)
+            .iter()
+            .map(|o| o.value(asset))
+            .collect(),
+        identity_id: asset.identity_id.clone(),
+    }
+}
+
+/// A SQL `ORDER BY` list for the given sort order, optionally reversed.
+pub fn order_by_sql(orders: &[BalanceOrder], reverse: bool) -> String {
+    let direction = |descending: bool| {
+        if descending != reverse { "DESC" } else { "ASC" }
+    };
+
+    effective_order(orders)
+        .iter()
+        .map(|o| format!("{} {}", o.column(), direction(o.is_descending())))
+        .chain(std::iter::once(format!("identity_id {}", direction(false))))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A short, stable fingerprint of a sort order, so cursors can tell which order they were created for
+/// without carrying its SQL.
+pub fn order_fingerprint(orders: &[BalanceOrder]) -> String {
+    let digest = Sha256::digest(order_by_sql(orders, false).as_bytes());
+    digest[..8].iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// A SQL boolean expression matching the assets that sort strictly after (or before) the key,
+/// along with the parameters it binds in order.
+///
+/// The predicate expands the lexicographic comparison of the sort columns, so it works with
+/// mixed sort directions.
+pub fn keyset_sql(
+    orders: &[BalanceOrder],
+    key: &BalanceKey,
+    after: bool,
+) -> anyhow::Result<(String, Vec<Box<dyn ToSql>>)> {
+    let orders = effective_order(orders);
+    if orders.len() != key.values.len() {
+        anyhow::bail!("The key does not match the sort order");
+    }
+
+    let mut columns: Vec<(&str, bool, SortValue)> = orders
+        .iter()
+        .zip(&key.values)
+        .map(|(o, v)| (o.column(), o.is_descending(), v.clone()))
+        .collect();
+    columns.push(("identity_id", false, SortValue::Text(key.identity_id.clone())));
+
+    let mut alternatives = Vec::with_capacity(columns.len());
+    let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+    for index in 0..columns.len() {
+        let mut conditions = Vec::with_capacity(index + 1);
+
+        for (column, _, value) in &columns[..index] {
+            conditions.push(format!("{column} = ?"));
+            params.push(Box::new(value.clone()));
+        }
+
+        let (column, descending, value) = &columns[index];
+        let operator = if *descending != after { ">" } else { "<" };
+        conditions.push(format!("{column} {operator} ?"));
+        params.push(Box::new(value.clone()));
+
+        alternatives.push(format!("({})", conditions.join(" AND ")));
+    }
+
+    Ok((format!("({})", alternatives.join(" OR ")), params))
+}
@@ -0,0 +1,3 @@
+SELECT id, name, scopes, tenant_id, created_at, revoked_at
+FROM api_keys
+ORDER BY created_at ASC, id ASC;
@@ -0,0 +1,6 @@
+SELECT recorded_at, action, asset_id, balance_as_of, details
+FROM audit_trail
+JOIN asset_identities USING (asset_id)
+WHERE identity_id = ?
+  AND in_tenant(tenant_id, ?)
+ORDER BY recorded_at ASC;
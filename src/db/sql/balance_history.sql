@@ -0,0 +1,10 @@
+WITH snapshots AS (
+    SELECT balance_as_of, balance_current
+    FROM known_snapshots($7, $5)
+    WHERE identity_id = (SELECT identity_id FROM asset_identities WHERE asset_id = $1)
+)
+SELECT buckets.starts_at, buckets.closes_at, snapshots.balance_current, snapshots.balance_as_of
+FROM buckets($2, $3, $4, CAST($6 AS INTERVAL)) AS buckets
+ASOF LEFT JOIN snapshots
+    ON buckets.closes_at >= snapshots.balance_as_of
+ORDER BY buckets.starts_at ASC;
\ No newline at end of file
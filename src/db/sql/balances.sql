@@ -1,10 +1,6 @@
 SELECT *
-FROM assets
-WHERE balance_as_of <= ?
-WINDOW
-    my_window AS (PARTITION BY nickname ORDER BY balance_as_of DESC)
-QUALIFY
-    row_number() OVER my_window == 1
-ORDER BY nickname ASC
-LIMIT ?
-OFFSET ?;
\ No newline at end of file
+FROM latest_snapshots(?, ?, ?)
+WHERE {filter}
+  AND {keyset}
+ORDER BY {order}
+LIMIT ?;
\ No newline at end of file
@@ -0,0 +1,3 @@
+UPDATE asset_identities
+SET tenant_id = ?
+WHERE tenant_id IS NULL;
\ No newline at end of file
@@ -1,3 +1,3 @@
-SELECT COUNT(DISTINCT nickname)
-FROM assets
-WHERE balance_as_of <= ?;
\ No newline at end of file
+SELECT COUNT(*)
+FROM latest_snapshots(?, ?, ?)
+WHERE {filter};
\ No newline at end of file
@@ -0,0 +1,4 @@
+SELECT COUNT(*)
+FROM asset_identities
+WHERE identity_id = ?
+  AND in_tenant(tenant_id, ?);
\ No newline at end of file
@@ -0,0 +1,4 @@
+UPDATE asset_identities
+SET identity_id = asset_id
+WHERE asset_id = ?
+  AND in_tenant(tenant_id, ?);
\ No newline at end of file
@@ -0,0 +1,4 @@
+SELECT id, name, scopes, tenant_id, created_at, revoked_at
+FROM api_keys
+WHERE hash = ?
+  AND revoked_at IS NULL;
@@ -0,0 +1,7 @@
+SELECT *
+FROM latest_snapshots(?, ?, ?)
+WHERE identity_id IN (
+    SELECT identity_id
+    FROM asset_identities
+    WHERE asset_id = ?
+);
@@ -0,0 +1,8 @@
+SELECT *
+FROM latest_snapshots(?, ?, ?)
+WHERE identity_id IN (
+    SELECT identity_id
+    FROM known_snapshots(?, ?)
+    WHERE wid = ?
+)
+ORDER BY identity_id;
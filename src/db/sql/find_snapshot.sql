@@ -0,0 +1,4 @@
+SELECT *
+FROM known_snapshots(?, ?)
+WHERE asset_id = ?
+  AND balance_as_of = ?;
@@ -0,0 +1,3 @@
+SELECT identity_id, tenant_id
+FROM asset_identities
+WHERE asset_id = ?;
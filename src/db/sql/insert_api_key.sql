@@ -0,0 +1,2 @@
+INSERT INTO api_keys (id, name, hash, scopes, tenant_id, created_at)
+VALUES (?, ?, ?, ?, ?, ?);
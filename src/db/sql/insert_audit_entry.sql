@@ -0,0 +1,2 @@
+INSERT INTO audit_trail (recorded_at, action, asset_id, balance_as_of, details)
+VALUES (?, ?, ?, ?, ?);
@@ -0,0 +1,11 @@
+SELECT
+    primary_asset_category,
+    wealth_asset_type,
+    COALESCE(SUM(balance_current) FILTER (WHERE is_asset), 0) AS assets,
+    COALESCE(SUM(balance_current) FILTER (WHERE NOT is_asset), 0) AS liabilities,
+    GROUPING(primary_asset_category) = 1 AS all_categories,
+    GROUPING(wealth_asset_type) = 1 AS all_types,
+FROM latest_snapshots(?, ?, ?)
+WHERE include_in_net_worth
+GROUP BY GROUPING SETS ((), (primary_asset_category), (wealth_asset_type))
+ORDER BY all_categories DESC, all_types DESC, primary_asset_category, wealth_asset_type;
\ No newline at end of file
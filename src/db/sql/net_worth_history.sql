@@ -0,0 +1,20 @@
+WITH snapshots AS (
+    SELECT *
+    FROM known_snapshots($6, $4)
+),
+grid AS (
+    SELECT *
+    FROM buckets($1, $2, $3, CAST($5 AS INTERVAL))
+    LEFT JOIN (SELECT DISTINCT identity_id FROM snapshots) AS identities ON true
+)
+SELECT
+    grid.starts_at,
+    grid.closes_at,
+    COALESCE(SUM(balance_current) FILTER (WHERE include_in_net_worth AND is_asset), 0) AS assets,
+    COALESCE(SUM(balance_current) FILTER (WHERE include_in_net_worth AND NOT is_asset), 0) AS liabilities,
+FROM grid
+ASOF LEFT JOIN snapshots
+    ON grid.identity_id = snapshots.identity_id
+    AND grid.closes_at >= snapshots.balance_as_of
+GROUP BY grid.starts_at, grid.closes_at
+ORDER BY grid.starts_at ASC;
\ No newline at end of file
@@ -0,0 +1,9 @@
+SELECT nickname, balance_as_of
+FROM assets
+JOIN asset_identities USING (asset_id)
+WHERE identity_id = ?
+  AND in_tenant(tenant_id, ?)
+  AND superseded_at IS NULL
+QUALIFY
+    nickname IS DISTINCT FROM lag(nickname) OVER (ORDER BY balance_as_of ASC)
+ORDER BY balance_as_of ASC;
\ No newline at end of file
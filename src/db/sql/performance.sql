@@ -0,0 +1,9 @@
+SELECT
+    ending.identity_id,
+    ending.nickname,
+    starting.balance_current,
+    ending.balance_current,
+    ending.balance_cost_basis,
+FROM latest_snapshots($4, $2, $3) AS ending
+LEFT JOIN latest_snapshots($4, $1, $3) AS starting USING (identity_id)
+ORDER BY ending.nickname ASC, ending.identity_id ASC;
\ No newline at end of file
@@ -0,0 +1,32 @@
+-- Databases created before snapshots were versioned only allow a single snapshot per asset id.
+-- Keys can't be altered, so their snapshots are copied into a table with the versioned primary key instead.
+CREATE TABLE assets_versioned (
+    asset_id VARCHAR(255) NOT NULL,
+    balance_as_of DATETIME NOT NULL,
+    balance_current DOUBLE NOT NULL,
+    creation_date DATETIME NOT NULL,
+    deactivate_by DATETIME,
+    include_in_net_worth BOOLEAN NOT NULL,
+    is_active BOOLEAN NOT NULL,
+    is_asset BOOLEAN NOT NULL,
+    is_favorite BOOLEAN NOT NULL,
+    last_update DATETIME NOT NULL,
+    last_update_attempt DATETIME NOT NULL,
+    modification_date DATETIME NOT NULL,
+    nickname VARCHAR(255) NOT NULL,
+    primary_asset_category VARCHAR(255) NOT NULL,
+    wealth_asset_type VARCHAR(255) NOT NULL,
+    wid HUGEINT NOT NULL,
+    recorded_at DATETIME NOT NULL,
+    superseded_at DATETIME,
+    balance_cost_basis DOUBLE,
+    PRIMARY KEY (asset_id, balance_as_of, recorded_at),
+);
+
+INSERT INTO assets_versioned BY NAME
+SELECT *
+FROM assets;
+
+DROP TABLE assets;
+
+ALTER TABLE assets_versioned RENAME TO assets;
@@ -0,0 +1,3 @@
+INSERT INTO asset_identities (asset_id, identity_id, tenant_id)
+VALUES (?, ?, ?)
+ON CONFLICT DO NOTHING;
\ No newline at end of file
@@ -0,0 +1,7 @@
+SELECT *
+FROM known_snapshots(?, ?)
+WHERE identity_id = ?
+  AND {range}
+  AND {keyset}
+ORDER BY balance_as_of DESC, asset_id DESC
+LIMIT ?;
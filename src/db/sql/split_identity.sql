@@ -0,0 +1,10 @@
+UPDATE asset_identities
+SET identity_id = (
+    SELECT min(asset_id)
+    FROM asset_identities
+    WHERE identity_id = $1
+      AND asset_id <> $1
+)
+WHERE identity_id = $1
+  AND asset_id <> $1
+  AND in_tenant(tenant_id, $2);
\ No newline at end of file
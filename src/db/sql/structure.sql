@@ -1,5 +1,5 @@
 CREATE TABLE IF NOT EXISTS assets (
-    asset_id VARCHAR(255) PRIMARY KEY NOT NULL,
+    asset_id VARCHAR(255) NOT NULL,
     balance_as_of DATETIME NOT NULL,
     balance_current DOUBLE NOT NULL,
     creation_date DATETIME NOT NULL,
@@ -15,4 +15,109 @@ CREATE TABLE IF NOT EXISTS assets (
     primary_asset_category VARCHAR(255) NOT NULL,
     wealth_asset_type VARCHAR(255) NOT NULL,
     wid HUGEINT NOT NULL,
+    -- System time: when this version of the snapshot was recorded and when a correction replaced it.
+    recorded_at DATETIME NOT NULL,
+    superseded_at DATETIME,
+    PRIMARY KEY (asset_id, balance_as_of, recorded_at),
+);
+
+-- Databases created before snapshots were versioned lack the system time. Their snapshots are treated as recorded
+-- when they were last modified. Their primary key is rebuilt by `rebuild_assets.sql`, as keys can't be altered.
+ALTER TABLE assets ADD COLUMN IF NOT EXISTS recorded_at DATETIME;
+ALTER TABLE assets ADD COLUMN IF NOT EXISTS superseded_at DATETIME;
+UPDATE assets SET recorded_at = modification_date WHERE recorded_at IS NULL;
+
+-- Added after the first release, so databases created before it lack the cost basis of their snapshots.
+ALTER TABLE assets ADD COLUMN IF NOT EXISTS balance_cost_basis DOUBLE;
+
+-- Groups the snapshots of one or more source asset ids under a stable identity, so renames do not split history.
+CREATE TABLE IF NOT EXISTS asset_identities (
+    asset_id VARCHAR(255) PRIMARY KEY NOT NULL,
+    identity_id VARCHAR(255) NOT NULL,
+);
+
+-- The tenant an asset id belongs to, set when it is first recorded. Empty for assets recorded without authentication.
+ALTER TABLE asset_identities ADD COLUMN IF NOT EXISTS tenant_id VARCHAR(255);
+
+-- Asset ids recorded before identities existed start out as their own identity.
+INSERT INTO asset_identities (asset_id, identity_id)
+SELECT DISTINCT asset_id, asset_id
+FROM assets
+ON CONFLICT DO NOTHING;
+
+-- Whether a row belongs to a tenant. Every row matches when no tenant is given.
+CREATE OR REPLACE MACRO in_tenant(tenant_id, tenant) AS
+    tenant IS NULL OR tenant_id = tenant;
+
+-- Every snapshot of a tenant grouped under its identity, as it was known at a given system time.
+CREATE OR REPLACE MACRO known_snapshots(tenant, known_at) AS TABLE
+    SELECT
+        asset_id,
+        balance_as_of,
+        balance_cost_basis,
+        balance_current,
+        creation_date,
+        deactivate_by,
+        include_in_net_worth,
+        is_active,
+        is_asset,
+        is_favorite,
+        last_update,
+        last_update_attempt,
+        modification_date,
+        nickname,
+        primary_asset_category,
+        wealth_asset_type,
+        wid,
+        recorded_at,
+        asset_identities.identity_id,
+        asset_identities.tenant_id,
+    FROM assets
+    JOIN asset_identities USING (asset_id)
+    WHERE in_tenant(asset_identities.tenant_id, tenant)
+      AND recorded_at <= known_at
+      AND (superseded_at IS NULL OR superseded_at > known_at);
+
+-- The latest snapshot of every asset identity of a tenant as of a balance date, as it was known at a given system time.
+CREATE OR REPLACE MACRO latest_snapshots(tenant, as_of, known_at) AS TABLE
+    SELECT *
+    FROM known_snapshots(tenant, known_at)
+    WHERE balance_as_of <= as_of
+    QUALIFY
+        row_number() OVER (PARTITION BY identity_id ORDER BY balance_as_of DESC, recorded_at DESC, asset_id) == 1;
+
+-- Buckets of a fixed interval covering a date range. Each bucket closes at the end of its interval or the end of the range.
+CREATE OR REPLACE MACRO buckets(range_from, range_to, date_part, step) AS TABLE
+    SELECT
+        bucket AS starts_at,
+        LEAST(bucket + step - INTERVAL 1 MICROSECOND, CAST(range_to AS TIMESTAMP)) AS closes_at
+    FROM generate_series(
+        date_trunc(date_part, CAST(range_from AS TIMESTAMP)),
+        CAST(range_to AS TIMESTAMP),
+        step
+    ) AS buckets(bucket);
+
+-- Changes made to assets outside of imports, recorded at the same system time as the snapshot they produced.
+CREATE TABLE IF NOT EXISTS audit_trail (
+    recorded_at DATETIME NOT NULL,
+    action VARCHAR(255) NOT NULL,
+    asset_id VARCHAR(255) NOT NULL,
+    balance_as_of DATETIME NOT NULL,
+    details VARCHAR NOT NULL,
+);
+
+-- Keys for machine clients. Only the SHA-256 hash of a key is stored, and revoked keys are kept for auditing.
+CREATE TABLE IF NOT EXISTS api_keys (
+    id VARCHAR(255) PRIMARY KEY NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    hash VARCHAR(64) NOT NULL UNIQUE,
+    scopes VARCHAR NOT NULL,
+    tenant_id VARCHAR(255),
+    created_at DATETIME NOT NULL,
+    revoked_at DATETIME,
+);
+
+-- The version of this structure the database was last set up with, so servers can tell whether it is current.
+CREATE TABLE IF NOT EXISTS schema_version (
+    version INTEGER NOT NULL,
 );
@@ -0,0 +1,5 @@
+UPDATE assets
+SET superseded_at = ?
+WHERE asset_id = ?
+  AND balance_as_of = ?
+  AND superseded_at IS NULL;
\ No newline at end of file
@@ -0,0 +1,5 @@
+SELECT count(*) > 0
+FROM duckdb_constraints()
+WHERE table_name = 'assets'
+  AND constraint_type = 'PRIMARY KEY'
+  AND NOT list_contains(constraint_column_names, 'recorded_at');
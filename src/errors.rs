@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextExecute};
+use async_graphql::{Response, ServerError};
+
+/// The message clients receive instead of the details of an internal error.
+const INTERNAL_ERROR: &str = "Internal server error";
+
+/// Replaces the messages of internal errors, such as database failures, with a generic message and logs the details.
+///
+/// Internal errors are those raised from an `anyhow::Error` by a resolver. Errors about the request itself,
+/// like invalid arguments or cursors, are returned unchanged.
+pub struct MaskInternalErrors;
+
+impl ExtensionFactory for MaskInternalErrors {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(MaskInternalErrorsExtension)
+    }
+}
+
+struct MaskInternalErrorsExtension;
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for MaskInternalErrorsExtension {
+    async fn execute(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        operation_name: Option<&str>,
+        next: NextExecute<'_>,
+    ) -> Response {
+        let mut response = next.run(ctx, operation_name).await;
+
+        for error in &mut response.errors {
+            mask(error, operation_name);
+        }
+
+        response
+    }
+}
+
+fn mask(error: &mut ServerError, operation_name: Option<&str>) {
+    if error.source::<anyhow::Error>().is_some() {
+        tracing::error!(operation_name, path = ?error.path, message = %error.message, "Internal error in a GraphQL operation");
+        error.message = INTERNAL_ERROR.to_string();
+        error.source = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_graphql::{EmptyMutation, EmptySubscription, Error, Object, Result, Schema};
+
+    struct Query;
+
+    #[Object]
+    impl Query {
+        async fn internal(&self) -> Result<i32> {
+            Err(anyhow::anyhow!("connection refused"))?
+        }
+
+        async fn invalid(&self) -> Result<i32> {
+            Err(Error::new("`from` must not be after `to`"))
+        }
+    }
+
+    #[tokio::test]
+    async fn internal_error_details_are_hidden() {
+        let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+            .extension(MaskInternalErrors)
+            .finish();
+
+        let internal = schema.execute("{ internal }").await;
+        assert_eq!(INTERNAL_ERROR, internal.errors[0].message);
+
+        let invalid = schema.execute("{ invalid }").await;
+        assert_eq!("`from` must not be after `to`", invalid.errors[0].message);
+    }
+}
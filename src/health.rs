@@ -0,0 +1,171 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use axum::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::db::{AssetRepository, SCHEMA_VERSION};
+
+/// Decides whether the server is ready to take traffic.
+#[derive(Clone)]
+pub struct Readiness {
+    repository: AssetRepository,
+    /// How long to wait for a database connection.
+    deadline: Duration,
+    /// How old the most recent snapshot may be. Freshness isn't checked without it.
+    max_data_age: Option<Duration>,
+    shutting_down: Arc<AtomicBool>,
+}
+
+/// The result of one readiness check, along with what was checked.
+#[derive(Debug, Serialize)]
+pub struct Component {
+    ready: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(flatten)]
+    details: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Component {
+    fn new(ready: bool) -> Self {
+        Self {
+            ready,
+            error: None,
+            details: serde_json::Map::new(),
+        }
+    }
+
+    fn failed(error: impl ToString) -> Self {
+        Self {
+            ready: false,
+            error: Some(error.to_string()),
+            details: serde_json::Map::new(),
+        }
+    }
+
+    fn with(mut self, key: &str, value: impl Serialize) -> Self {
+        self.details.insert(key.to_string(), serde_json::json!(value));
+        self
+    }
+}
+
+/// The readiness of the server and each of its components, reported with `503 Service Unavailable` when not ready.
+#[derive(Debug)]
+pub struct Report {
+    ready: bool,
+    components: Vec<(&'static str, Component)>,
+}
+
+impl IntoResponse for Report {
+    fn into_response(self) -> Response {
+        let status = if self.ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        let components: serde_json::Map<_, _> = self
+            .components
+            .into_iter()
+            .map(|(name, component)| (name.to_string(), serde_json::json!(component)))
+            .collect();
+
+        (status, Json(serde_json::json!({ "ready": self.ready, "components": components }))).into_response()
+    }
+}
+
+impl Readiness {
+    pub fn new(repository: AssetRepository, deadline: Duration, max_data_age: Option<Duration>) -> Self {
+        Self {
+            repository,
+            deadline,
+            max_data_age,
+            shutting_down: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Reports the server as not ready from now on, so traffic is drained before it stops.
+    pub fn shut_down(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    /// Checks every component. Blocks on the database for up to the deadline.
+    pub fn check(&self, now: DateTime<Utc>) -> Report {
+        let shutdown = Component::new(!self.shutting_down.load(Ordering::SeqCst));
+
+        let started = Instant::now();
+        let pool = match self.repository.ping(self.deadline) {
+            Ok(()) => Component::new(true).with("latencyMs", started.elapsed().as_millis() as u64),
+            Err(e) => Component::failed(e),
+        };
+        // The other checks wait for a connection as long as queries do, so they are skipped when none is available.
+        if !pool.ready {
+            let skipped = || Component::failed("Skipped without a database connection");
+            return Report {
+                ready: false,
+                components: vec![("shutdown", shutdown), ("pool", pool), ("schema", skipped()), ("freshness", skipped())],
+            };
+        }
+
+        let schema = match self.repository.schema_version() {
+            Ok(version) => Component::new(version == Some(SCHEMA_VERSION))
+                .with("version", version)
+                .with("expected", SCHEMA_VERSION),
+            Err(e) => Component::failed(e),
+        };
+
+        let freshness = match self.repository.last_recorded_at() {
+            Ok(last_recorded_at) => {
+                let age = last_recorded_at.map(|recorded_at| (now - recorded_at).num_seconds().max(0) as u64);
+                let ready = match (self.max_data_age, age) {
+                    (None, _) => true,
+                    (Some(max_data_age), Some(age)) => age <= max_data_age.as_secs(),
+                    (Some(_), None) => false,
+                };
+
+                Component::new(ready)
+                    .with("lastRecordedAt", last_recorded_at)
+                    .with("ageSeconds", age)
+                    .with("maxAgeSeconds", self.max_data_age.map(|max_data_age| max_data_age.as_secs()))
+            }
+            Err(e) => Component::failed(e),
+        };
+
+        let components = vec![("shutdown", shutdown), ("pool", pool), ("schema", schema), ("freshness", freshness)];
+        Report {
+            ready: components.iter().all(|(_, component)| component.ready),
+            components,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readiness_covers_freshness_and_shutdown() {
+        let directory = std::env::temp_dir().join(format!("assets-{}-readiness", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let repository = AssetRepository::new(directory.join("assets.db"), 1, Duration::from_secs(1)).unwrap();
+        repository.setup().unwrap();
+
+        let readiness = Readiness::new(repository.clone(), Duration::from_secs(1), None);
+        assert!(readiness.check(Utc::now()).ready);
+
+        // Without any snapshots, the data can't be fresh.
+        let fresh = Readiness::new(repository, Duration::from_secs(1), Some(Duration::from_secs(60)));
+        let report = fresh.check(Utc::now());
+        assert!(!report.ready);
+        assert!(report.components.iter().all(|(name, component)| component.ready == (*name != "freshness")));
+
+        readiness.shut_down();
+        assert!(!readiness.check(Utc::now()).ready);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
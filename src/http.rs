@@ -1,17 +1,22 @@
-use crate::options::StartOptions;
-use crate::{api, db, import};
-use anyhow::anyhow;
-use axum::extract::State;
-use axum::http::StatusCode;
+use crate::auth::{Authentication, Authenticator, Principal, authorize};
+use crate::db::Scope;
+use crate::options::{Mode, Role, StartOptions};
+use crate::publish::Publisher;
+use crate::ratelimit::{RateLimiter, RateLimits, Route};
+use crate::health::Readiness;
+use crate::{api, auth, identity, import, keys, metrics, persisted, ratelimit, watch};
+use anyhow::bail;
+use axum::extract::{Path, State, WebSocketUpgrade};
+use axum::http::{HeaderMap, StatusCode, header};
 use axum::response::{Html, IntoResponse, Response};
-use axum::routing::{get, post};
-use axum::{Json, Router, serve};
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
-use std::time::Duration;
-use async_graphql::{EmptyMutation, EmptySubscription, Schema};
-use async_graphql::http::GraphiQLSource;
-use async_graphql_axum::GraphQL;
+use axum::routing::{delete, get, post};
+use axum::{Extension, Json, Router, middleware, serve};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use async_graphql::Data;
+use async_graphql::http::{ALL_WEBSOCKET_PROTOCOLS, GraphiQLSource};
+use async_graphql_axum::{GraphQLBatchRequest, GraphQLProtocol, GraphQLResponse, GraphQLWebSocket};
 use tokio::net::TcpListener;
 use tokio::signal;
 use tower_http::timeout::TimeoutLayer;
@@ -23,76 +28,361 @@ use crate::db::AssetRepository;
 #[derive(Clone)]
 pub struct ServerState {
     pub asset_repository: AssetRepository,
+    pub schema: api::AssetSchema,
+    pub rate_limiter: RateLimiter,
+    pub publisher: Option<Publisher>,
+    pub readiness: Readiness,
+    pub database_path: PathBuf,
+    /// How subscriptions authenticate when their upgrade request carries no credentials, if authentication is enabled.
+    pub authentication: Option<Authentication>,
 }
 
 impl ServerState {
     /// Creates a new instance of the server state.
-    pub fn new(asset_repository: AssetRepository) -> Self {
-        Self { asset_repository }
+    pub fn new(
+        asset_repository: AssetRepository,
+        schema: api::AssetSchema,
+        rate_limiter: RateLimiter,
+        publisher: Option<Publisher>,
+        readiness: Readiness,
+        database_path: PathBuf,
+        authentication: Option<Authentication>,
+    ) -> Self {
+        Self {
+            asset_repository,
+            schema,
+            rate_limiter,
+            publisher,
+            readiness,
+            database_path,
+            authentication,
+        }
+    }
+
+    /// Publishes the database again after a change that isn't a commit of snapshots, if it is published.
+    fn changed(&self) {
+        if let Some(publisher) = &self.publisher {
+            publisher.changed();
+        }
     }
 }
 
+/// The asset repository scoped to the tenant a request was authenticated for, or unscoped without authentication.
+fn scoped_repository(server_state: &ServerState, principal: Option<&Principal>) -> AssetRepository {
+    server_state
+        .asset_repository
+        .scoped(principal.and_then(|principal| principal.tenant.clone()))
+}
 
-pub async fn start_server(start_options: &StartOptions) -> anyhow::Result<()> {
-    let asset_repository =
-        AssetRepository::new(&start_options.database_path, 5, Duration::from_secs(10))?;
 
-    asset_repository.setup()?;
+pub async fn start_server(start_options: &StartOptions) -> anyhow::Result<()> {
+    let role = start_options.role;
+    let asset_repository = if role == Role::Query {
+        if start_options.publish_path.is_some() {
+            bail!("Query instances can't publish the database, since they don't change it");
+        }
+        if start_options.default_tenant.is_some() {
+            bail!("Query instances can't assign a default tenant, since they don't change the database");
+        }
+        let asset_repository =
+            AssetRepository::open_read_only(&start_options.database_path, 5, Duration::from_secs(10))?;
+        if start_options.watch {
+            let watched = start_options.version_file.as_ref().unwrap_or(&start_options.database_path);
+            watch::start(
+                asset_repository.clone(),
+                watched.clone(),
+                Duration::from_secs(start_options.watch_interval),
+            );
+        }
+        asset_repository
+    } else {
+        if start_options.watch {
+            bail!("Only query instances can watch the database, since other roles open it for writing");
+        }
+        let asset_repository = AssetRepository::new(&start_options.database_path, 5, Duration::from_secs(10))?;
+        asset_repository.setup()?;
+        if let Some(tenant) = &start_options.default_tenant {
+            let claimed = asset_repository.claim_unowned(tenant)?;
+            tracing::info!(claimed, tenant = %tenant, "Assigned identities without a tenant to the default tenant");
+        }
+        asset_repository
+    };
+    let publisher = start_options.publish_path.as_ref().map(|path| {
+        Publisher::start(
+            asset_repository.clone(),
+            path.clone(),
+            start_options.notify.clone(),
+            start_options.notify_api_key.clone(),
+        )
+    });
 
     let listener = TcpListener::bind(&start_options.address).await?;
-    let schema = Schema::build(api::QueryRoot, EmptyMutation, EmptySubscription)
-        .data(asset_repository.clone())
-        .finish();
-    let app = Router::new()
-        .route("/", get(graphiql).post_service(GraphQL::new(schema)))
-        .route("/import", post(import_assets))
+    let defaults = match start_options.mode {
+        Mode::Production => api::Limits::production(),
+        Mode::Development => api::Limits::default(),
+    };
+    let limits = api::Limits {
+        max_depth: start_options.max_depth.unwrap_or(defaults.max_depth),
+        max_complexity: start_options.max_complexity.unwrap_or(defaults.max_complexity),
+        max_page_size: start_options.max_page_size.unwrap_or(defaults.max_page_size),
+        max_history_points: start_options.max_history_points.unwrap_or(defaults.max_history_points),
+    };
+    let manifest = start_options
+        .persisted_query_manifest
+        .as_ref()
+        .map(persisted::Manifest::load)
+        .transpose()?;
+    let authenticator = match &start_options.jwks {
+        Some(jwks) => Some(
+            Authenticator::load(
+                jwks,
+                start_options.jwt_issuer.clone(),
+                start_options.jwt_audience.clone(),
+                start_options.jwt_scopes.clone(),
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    let readiness = Readiness::new(
+        asset_repository.clone(),
+        Duration::from_millis(start_options.readiness_timeout),
+        start_options.max_data_age.map(Duration::from_secs),
+    );
+    let schema = api::build_schema(asset_repository.clone(), limits, manifest, start_options.mode, role);
+    let rate_limiter = RateLimiter::new(RateLimits {
+        import_per_minute: start_options.import_rate_limit,
+        graphql_per_minute: start_options.graphql_rate_limit,
+        daily_import_rows: start_options.daily_import_quota,
+    });
+    let limit = |route| middleware::from_fn_with_state(rate_limiter.route(route), ratelimit::limit);
+    let authentication = (authenticator.is_some() || start_options.require_auth).then(|| Authentication {
+        tokens: authenticator,
+        keys: asset_repository.clone(),
+    });
+
+    // Rate limits are layered on these routes before authentication is, so they run after it and can tell clients apart.
+    // GraphQL is served by every role, which rejects the operations of other roles.
+    let mut app = Router::new().route("/", post(graphql).layer(limit(Route::Graphql)));
+    if role.serves(Role::Import) {
+        app = app
+            .route("/import", post(import_assets).layer(limit(Route::Import)))
+            .route("/identities/merge", post(merge_identities).layer(limit(Route::Import)))
+            .route("/identities/split", post(split_identity).layer(limit(Route::Import)));
+    }
+    // Without authentication there is no principal to require the admin scope of, so keys are only managed over the API
+    // when it is enabled. The first key is created directly in the database.
+    if role.serves(Role::Import) && authentication.is_some() {
+        app = app
+            .route("/keys", get(list_keys).post(create_key))
+            .route("/keys/{id}", delete(revoke_key));
+    }
+    // Reloading is only served with authentication enabled, so anonymous clients can't make the server reopen its database.
+    if role == Role::Query && authentication.is_some() {
+        app = app.route("/reload", post(reload));
+    } else if role == Role::Query && !start_options.watch {
+        tracing::warn!("Without authentication /reload is not served, so the database is only reloaded with --watch");
+    }
+    if let Some(authentication) = &authentication {
+        app = app.route_layer(middleware::from_fn_with_state(authentication.clone(), auth::authenticate));
+    }
+    // Subscriptions are served over the graphql-ws protocol. They authenticate themselves, since browsers can't send
+    // headers with the upgrade request and pass credentials in the `connection_init` payload instead. For the same
+    // reason they are rate limited once their principal is known, rather than by a layer that runs before it is.
+    if role.serves(Role::Import) {
+        app = app.route("/ws", get(graphql_ws));
+    }
+    // GraphiQL is only served in development, so the public endpoint doesn't expose a playground.
+    // It doesn't need a token itself, as tokens are sent with the operations it runs.
+    if start_options.mode == Mode::Development {
+        app = app.route("/", get(graphiql));
+    }
+    // Probes and metrics are neither authenticated nor rate limited, so orchestrators and Prometheus can always reach them.
+    app = app
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(render_metrics));
+
+    let app = app
         .layer((
             TraceLayer::new_for_http(),
+            middleware::from_fn(metrics::track),
             // Graceful shutdown will wait for outstanding requests to complete. Add a timeout so
             // requests don't hang forever.
             TimeoutLayer::new(Duration::from_secs(300)),
         ))
-        .with_state(ServerState::new(asset_repository));
+        .with_state(ServerState::new(
+            asset_repository,
+            schema,
+            rate_limiter,
+            publisher,
+            readiness.clone(),
+            start_options.database_path.clone(),
+            authentication,
+        ));
 
+    let grace_period = Duration::from_secs(start_options.shutdown_grace_period);
     serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            readiness.shut_down();
+            tracing::info!(?grace_period, "Shutting down, reporting not ready");
+            tokio::time::sleep(grace_period).await;
+        })
         .await?;
 
     Ok(())
 }
 
 async fn graphiql() -> impl IntoResponse {
-    Html(GraphiQLSource::build().endpoint("/").finish())
+    Html(
+        GraphiQLSource::build()
+            .endpoint("/")
+            .subscription_endpoint("/ws")
+            .finish(),
+    )
+}
+
+// Operations check the scopes of the principal themselves, since queries and mutations need different scopes.
+async fn graphql(
+    State(server_state): State<ServerState>,
+    principal: Option<Extension<Principal>>,
+    request: GraphQLBatchRequest,
+) -> GraphQLResponse {
+    let principal = principal.map(|Extension(principal)| principal);
+    let mut request = request
+        .into_inner()
+        .data(scoped_repository(&server_state, principal.as_ref()))
+        .data(server_state.rate_limiter.quota(ratelimit::client_of(principal.as_ref())));
+    if let Some(principal) = principal {
+        request = request.data(principal);
+    }
+
+    server_state.schema.execute_batch(request).await.into()
+}
+
+// Credentials in the upgrade request's headers take precedence over those in the `connection_init` payload.
+async fn graphql_ws(
+    State(server_state): State<ServerState>,
+    headers: HeaderMap,
+    protocol: GraphQLProtocol,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let Some(authentication) = server_state.authentication.clone() else {
+        return upgrade_ws(server_state, None, protocol, upgrade);
+    };
+    if Authentication::has_credentials(&headers) {
+        return match authentication.principal_of(&headers).await {
+            Ok(principal) => upgrade_ws(server_state, Some(principal), protocol, upgrade),
+            Err(e) => auth::unauthorized(e),
+        };
+    }
+
+    upgrade
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |stream| {
+            let schema = server_state.schema.clone();
+            GraphQLWebSocket::new(stream, schema, protocol)
+                .on_connection_init(move |payload| async move {
+                    let principal = match authentication.principal_of_payload(&payload).await {
+                        Ok(principal) => principal,
+                        Err(e) => {
+                            tracing::debug!(%e, "Rejected unauthenticated subscription");
+                            return Err(async_graphql::Error::new("Unauthorized"));
+                        }
+                    };
+                    match limit_ws(&server_state, Some(&principal)) {
+                        Ok(()) => Ok(connection_data(&server_state, Some(principal))),
+                        Err(throttled) => Err(async_graphql::Error::new(throttled.message)),
+                    }
+                })
+                .serve()
+        })
+}
+
+/// Serves subscriptions for a principal that is already known, or without one when authentication is disabled.
+fn upgrade_ws(
+    server_state: ServerState,
+    principal: Option<Principal>,
+    protocol: GraphQLProtocol,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    if let Err(throttled) = limit_ws(&server_state, principal.as_ref()) {
+        return throttled.into_response();
+    }
+    let data = connection_data(&server_state, principal);
+
+    upgrade
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |stream| {
+            GraphQLWebSocket::new(stream, server_state.schema, protocol)
+                .with_data(data)
+                .serve()
+        })
+}
+
+/// Counts a subscription connection against the GraphQL rate limit of its principal.
+fn limit_ws(server_state: &ServerState, principal: Option<&Principal>) -> Result<(), ratelimit::Throttled> {
+    let client = ratelimit::client_of(principal);
+    server_state.rate_limiter.check(Route::Graphql, &client, Instant::now()).inspect_err(|_| {
+        tracing::debug!(%client, "Throttled subscription");
+    })
+}
+
+/// The data the operations of a subscription connection are executed with.
+fn connection_data(server_state: &ServerState, principal: Option<Principal>) -> Data {
+    let mut data = Data::default();
+    data.insert(scoped_repository(server_state, principal.as_ref()));
+    data.insert(server_state.rate_limiter.quota(ratelimit::client_of(principal.as_ref())));
+    if let Some(principal) = principal {
+        data.insert(principal);
+    }
+
+    data
 }
 
 async fn import_assets(
     State(server_state): State<ServerState>,
+    principal: Option<Extension<Principal>>,
     // Ideally we would check whether we already imported these assets to support idempotency.
     // One option would be to hash the assets with a cryptographically secure hash and store the hashes in the database.
     // Another options would be to allow the caller to pass in an idempotency key header.
     Json(assets): Json<Vec<import::Asset>>,
 ) -> Response {
-    let (good, bad): (Vec<_>, Vec<_>) = assets
-        .into_iter()
-        .map(import::Asset::try_into) // This is performing validation.
-        .enumerate()
-        .map(|(index, result)| {
-            result.map_err(|e| anyhow!("Failed to convert asset at index {}: {}", index, e))
-        })
-        .partition(Result::is_ok);
-
-    if !bad.is_empty() {
-        let errors = bad
-            .into_iter()
-            .filter_map(Result::err)
-            .map(|e: anyhow::Error| e.to_string())
-            .collect::<Vec<_>>();
-        return (StatusCode::BAD_REQUEST, Json(errors)).into_response();
+    if let Err(forbidden) = authorize(principal.as_deref(), Scope::ImportWrite) {
+        return forbidden.into_response();
     }
+    if let Some(tenant) = principal.as_deref().and_then(|principal| principal.tenant.as_ref())
+        && let Some(index) = assets.iter().position(|asset| asset.cognito_id != *tenant)
+    {
+        let error = format!("The asset at index {index} belongs to another tenant");
+        return (StatusCode::FORBIDDEN, Json(vec![error])).into_response();
+    }
+    let client = ratelimit::client_of(principal.as_deref());
+    let reservation = match server_state.rate_limiter.reserve_rows(&client, assets.len(), Utc::now()) {
+        Ok(reservation) => reservation,
+        Err(throttled) => return throttled.into_response(),
+    };
 
-    let assets: Vec<db::Asset> = good.into_iter().filter_map(Result::ok).collect();
-
-    match server_state.asset_repository.insert(assets) {
+    let asset_repository = scoped_repository(&server_state, principal.as_deref());
+    let result = import::import(&asset_repository, assets, import::ImportPolicy::default());
+    let recorded = result.as_ref().map_or(0, import::ImportReport::recorded);
+    server_state.rate_limiter.settle_rows(&client, reservation, recorded);
+    match result {
+        Ok(report) if !report.rejected.is_empty() => {
+            let errors = report
+                .rejected
+                .into_iter()
+                .map(|rejected| {
+                    format!(
+                        "Failed to convert asset at index {}: {}",
+                        rejected.index,
+                        import::ValidationError(rejected.errors)
+                    )
+                })
+                .collect::<Vec<_>>();
+            (StatusCode::BAD_REQUEST, Json(errors)).into_response()
+        }
         Ok(_) => StatusCode::CREATED.into_response(),
         Err(e) => {
             // at this point, we have already validated the assets, so this is likely a database error.
@@ -102,6 +392,162 @@ async fn import_assets(
     }
 }
 
+async fn merge_identities(
+    State(server_state): State<ServerState>,
+    principal: Option<Extension<Principal>>,
+    Json(request): Json<identity::MergeRequest>,
+) -> Response {
+    if let Err(forbidden) = authorize(principal.as_deref(), Scope::ImportWrite) {
+        return forbidden.into_response();
+    }
+    if request.target == request.source {
+        return (StatusCode::BAD_REQUEST, Json(vec!["Cannot merge an identity into itself"]))
+            .into_response();
+    }
+
+    match scoped_repository(&server_state, principal.as_deref()).merge_identities(&request.target, &request.source)
+    {
+        Ok(true) => {
+            server_state.changed();
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!(%e, "Failed to merge identities");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn split_identity(
+    State(server_state): State<ServerState>,
+    principal: Option<Extension<Principal>>,
+    Json(request): Json<identity::SplitRequest>,
+) -> Response {
+    if let Err(forbidden) = authorize(principal.as_deref(), Scope::ImportWrite) {
+        return forbidden.into_response();
+    }
+
+    match scoped_repository(&server_state, principal.as_deref()).split_identity(&request.asset_id) {
+        Ok(true) => {
+            server_state.changed();
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!(%e, "Failed to split identity");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn create_key(
+    State(server_state): State<ServerState>,
+    principal: Option<Extension<Principal>>,
+    Json(request): Json<keys::CreateKeyRequest>,
+) -> Response {
+    if let Err(forbidden) = authorize(principal.as_deref(), Scope::Admin) {
+        return forbidden.into_response();
+    }
+
+    let created = keys::generate(request).and_then(|created| {
+        server_state
+            .asset_repository
+            .create_api_key(&created.key, &keys::hash_of(&created.secret))?;
+        Ok(created)
+    });
+    match created {
+        Ok(created) => {
+            server_state.changed();
+            (StatusCode::CREATED, Json(created)).into_response()
+        }
+        Err(e) => {
+            tracing::error!(%e, "Failed to create API key");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn list_keys(State(server_state): State<ServerState>, principal: Option<Extension<Principal>>) -> Response {
+    if let Err(forbidden) = authorize(principal.as_deref(), Scope::Admin) {
+        return forbidden.into_response();
+    }
+
+    match server_state.asset_repository.api_keys() {
+        Ok(keys) => Json(keys).into_response(),
+        Err(e) => {
+            tracing::error!(%e, "Failed to list API keys");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn revoke_key(
+    State(server_state): State<ServerState>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+) -> Response {
+    if let Err(forbidden) = authorize(principal.as_deref(), Scope::Admin) {
+        return forbidden.into_response();
+    }
+
+    match server_state.asset_repository.revoke_api_key(&id) {
+        Ok(true) => {
+            server_state.changed();
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!(%e, "Failed to revoke API key");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Whether the process is alive. Doesn't touch the database, so a slow database doesn't get the server restarted.
+async fn healthz() -> Response {
+    Json(serde_json::json!({ "alive": true })).into_response()
+}
+
+/// Whether the server is ready to take traffic, with the status of each component.
+async fn readyz(State(server_state): State<ServerState>) -> Response {
+    let readiness = server_state.readiness.clone();
+
+    match tokio::task::spawn_blocking(move || readiness.check(Utc::now())).await {
+        Ok(report) => report.into_response(),
+        Err(e) => {
+            tracing::error!(%e, "Failed to check readiness");
+            StatusCode::SERVICE_UNAVAILABLE.into_response()
+        }
+    }
+}
+
+/// Every metric in the Prometheus text format.
+async fn render_metrics(State(server_state): State<ServerState>) -> Response {
+    match metrics::render(&server_state.asset_repository, &server_state.database_path) {
+        Ok(rendered) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], rendered).into_response(),
+        Err(e) => {
+            tracing::error!(%e, "Failed to render metrics");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Reopens the database of a query instance, after the import instance published a new copy.
+async fn reload(State(server_state): State<ServerState>, principal: Option<Extension<Principal>>) -> Response {
+    if let Err(forbidden) = authorize(principal.as_deref(), Scope::Admin) {
+        return forbidden.into_response();
+    }
+
+    match server_state.asset_repository.reload() {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!(%e, "Failed to reload the database");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -125,10 +571,3 @@ async fn shutdown_signal() {
         _ = terminate => {},
     }
 }
-
-/// Represents a request to get the balance of a specific asset as of a certain date.
-#[derive(Debug, Serialize, Deserialize)]
-struct BalanceRequest {
-    asset: String,
-    as_of: DateTime<Utc>,
-}
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+/// A request to merge the history of one asset identity into another.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeRequest {
+    /// The identity that is kept.
+    pub target: String,
+    /// The identity whose snapshots are regrouped under the target.
+    pub source: String,
+}
+
+/// A request to split an asset id out of the identity it is grouped under.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SplitRequest {
+    pub asset_id: String,
+}
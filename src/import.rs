@@ -1,15 +1,22 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt::{Display, Formatter};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use crate::db;
+use crate::db::AssetRepository;
+use crate::metrics;
 
 impl TryFrom<Asset> for db::Asset {
     type Error = anyhow::Error;
 
     fn try_from(asset: Asset) -> Result<Self, Self::Error> {
-        Ok(db::Asset {
+        Ok(Snapshot {
             asset_id: asset.asset_id,
             balance_as_of: asset.balance_as_of,
+            // The Wealth Import API reports an unknown cost basis as 0.
+            balance_cost_basis: asset.balance_cost_basis.filter(|cost_basis| *cost_basis != 0.0),
             balance_current: asset.balance_current,
             creation_date: asset.creation_date,
             deactivate_by: asset.deactivate_by,
@@ -23,9 +30,229 @@ impl TryFrom<Asset> for db::Asset {
             nickname: asset.nickname,
             primary_asset_category: asset.primary_asset_category.try_into()?,
             wealth_asset_type: asset.wealth_asset_type.try_into()?,
-            wid: asset.wid.as_u128() as i128,
-        })
+            wid: asset.wid,
+            tenant_id: Some(asset.cognito_id),
+        }
+        .into())
+    }
+}
+
+/// The attributes of an imported record that are stored, whether it was imported over HTTP or GraphQL.
+pub struct Snapshot {
+    pub asset_id: String,
+    pub balance_as_of: DateTime<Utc>,
+    pub balance_cost_basis: Option<f64>,
+    pub balance_current: f64,
+    pub creation_date: DateTime<Utc>,
+    pub deactivate_by: Option<DateTime<Utc>>,
+    pub include_in_net_worth: bool,
+    pub is_active: bool,
+    pub is_asset: bool,
+    pub is_favorite: bool,
+    pub last_update: DateTime<Utc>,
+    pub last_update_attempt: DateTime<Utc>,
+    pub modification_date: DateTime<Utc>,
+    pub nickname: String,
+    pub primary_asset_category: db::PrimaryAssetCategory,
+    pub wealth_asset_type: db::WealthAssetType,
+    pub wid: Uuid,
+    pub tenant_id: Option<String>,
+}
+
+impl From<Snapshot> for db::Asset {
+    fn from(snapshot: Snapshot) -> Self {
+        db::Asset {
+            // New assets start out as their own identity.
+            identity_id: snapshot.asset_id.clone(),
+            asset_id: snapshot.asset_id,
+            balance_as_of: snapshot.balance_as_of,
+            balance_cost_basis: snapshot.balance_cost_basis,
+            balance_current: snapshot.balance_current,
+            creation_date: snapshot.creation_date,
+            deactivate_by: snapshot.deactivate_by,
+            include_in_net_worth: snapshot.include_in_net_worth,
+            is_active: snapshot.is_active,
+            is_asset: snapshot.is_asset,
+            is_favorite: snapshot.is_favorite,
+            last_update: snapshot.last_update,
+            last_update_attempt: snapshot.last_update_attempt,
+            modification_date: snapshot.modification_date,
+            nickname: snapshot.nickname,
+            primary_asset_category: snapshot.primary_asset_category,
+            wealth_asset_type: snapshot.wealth_asset_type,
+            wid: snapshot.wid.as_u128() as i128,
+            tenant_id: snapshot.tenant_id,
+        }
+    }
+}
+
+/// The longest asset id or nickname the database can store.
+const MAX_LENGTH: usize = 255;
+
+/// Validates the rules every snapshot recorded through the GraphQL API must follow.
+pub fn validate(asset: &db::Asset) -> Result<(), ValidationError> {
+    let mut errors = Vec::new();
+
+    if asset.asset_id.is_empty() || asset.asset_id.chars().count() > MAX_LENGTH {
+        errors.push(FieldError::new("assetId", format!("must be between 1 and {MAX_LENGTH} characters")));
     }
+    if asset.nickname.trim().is_empty() || asset.nickname.chars().count() > MAX_LENGTH {
+        errors.push(FieldError::new("nickname", format!("must be between 1 and {MAX_LENGTH} characters and not blank")));
+    }
+    if !asset.balance_current.is_finite() {
+        errors.push(FieldError::new("balanceCurrent", "must be a finite number"));
+    }
+    if asset.balance_cost_basis.is_some_and(|cost_basis| !cost_basis.is_finite()) {
+        errors.push(FieldError::new("balanceCostBasis", "must be a finite number"));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ValidationError(errors))
+    }
+}
+
+/// A problem with a single field of a record.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// The field errors that make a record invalid.
+#[derive(Debug)]
+pub struct ValidationError(pub Vec<FieldError>);
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let errors: Vec<_> = self
+            .0
+            .iter()
+            .map(|e| format!("{} {}", e.field, e.message))
+            .collect();
+        write!(f, "{}", errors.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// How an import treats invalid records.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportPolicy {
+    /// Insert the valid records even when some are rejected. By default, nothing is inserted unless every record is valid.
+    pub allow_partial: bool,
+}
+
+/// A record that was recorded by an import, by its position in the request.
+#[derive(Debug)]
+pub struct ImportedRecord {
+    pub index: usize,
+    pub asset_id: String,
+    pub balance_as_of: DateTime<Utc>,
+}
+
+/// A record that was rejected by an import, by its position in the request.
+#[derive(Debug)]
+pub struct RejectedRecord {
+    pub index: usize,
+    pub errors: Vec<FieldError>,
+}
+
+/// The outcome of an import for every record.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Records of snapshots that were not known before.
+    pub inserted: Vec<ImportedRecord>,
+    /// Records that corrected a known snapshot.
+    pub updated: Vec<ImportedRecord>,
+    pub rejected: Vec<RejectedRecord>,
+}
+
+impl ImportReport {
+    /// The number of records that were recorded, which are the ones counted against import quotas.
+    pub fn recorded(&self) -> usize {
+        self.inserted.len() + self.updated.len()
+    }
+}
+
+/// Validates and converts records, then inserts them in a single transaction.
+///
+/// A batch records every snapshot at the same system time, so records of a snapshot that appeared earlier in the
+/// batch are rejected.
+///
+/// This is the pipeline shared by every import surface.
+pub fn import<T>(
+    asset_repository: &AssetRepository,
+    records: Vec<T>,
+    policy: ImportPolicy,
+) -> anyhow::Result<ImportReport>
+where
+    T: TryInto<db::Asset, Error = anyhow::Error>,
+{
+    let mut report = ImportReport::default();
+    let mut assets = Vec::with_capacity(records.len());
+    let mut keys = Vec::with_capacity(records.len());
+    let mut seen = HashMap::with_capacity(records.len());
+
+    for (index, record) in records.into_iter().enumerate() {
+        match record.try_into() {
+            Ok(asset) => {
+                match seen.entry((asset.asset_id.clone(), asset.balance_as_of)) {
+                    Entry::Occupied(first) => {
+                        let message = format!("duplicates the snapshot of the record at index {}", first.get());
+                        report.rejected.push(RejectedRecord {
+                            index,
+                            errors: vec![FieldError::new("balanceAsOf", message)],
+                        });
+                        continue;
+                    }
+                    Entry::Vacant(vacant) => {
+                        vacant.insert(index);
+                    }
+                }
+
+                keys.push(ImportedRecord {
+                    index,
+                    asset_id: asset.asset_id.clone(),
+                    balance_as_of: asset.balance_as_of,
+                });
+                assets.push(asset);
+            }
+            Err(e) => {
+                let errors = match e.downcast::<ValidationError>() {
+                    Ok(ValidationError(errors)) => errors,
+                    Err(e) => vec![FieldError::new("", e.to_string())],
+                };
+                report.rejected.push(RejectedRecord { index, errors });
+            }
+        }
+    }
+
+    if !report.rejected.is_empty() && !policy.allow_partial {
+        metrics::record_import(&report);
+        return Ok(report);
+    }
+
+    let recorded = asset_repository.insert(assets).inspect_err(|_| metrics::record_import_failure())?;
+    for (key, recorded) in keys.into_iter().zip(recorded) {
+        match recorded {
+            db::Recorded::Inserted => report.inserted.push(key),
+            db::Recorded::Updated => report.updated.push(key),
+        }
+    }
+
+    metrics::record_import(&report);
+    Ok(report)
 }
 
 impl TryFrom<PrimaryAssetCategory> for db::PrimaryAssetCategory {
@@ -72,7 +299,7 @@ pub struct Asset {
     // All null in sample data, assuming to be a String.
     pub asset_owner_name: Option<String>,
     pub balance_as_of: DateTime<Utc>,
-    pub balance_cost_basis: f64,
+    pub balance_cost_basis: Option<f64>,
     pub balance_cost_from: BalanceCostFrom,
     pub balance_current: f64,
     pub balance_from: BalanceFrom,
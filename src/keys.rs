@@ -0,0 +1,78 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::db::{ApiKey, Scope};
+
+/// The prefix of every secret key, so leaked keys are easy to recognize.
+const PREFIX: &str = "ak_";
+
+/// The header clients send their secret key in.
+pub const HEADER: &str = "x-api-key";
+
+/// The request body for creating an API key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateKeyRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub tenant_id: Option<String>,
+}
+
+/// A newly created API key along with its secret, which is only ever shown once.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedKey {
+    #[serde(flatten)]
+    pub key: ApiKey,
+    pub secret: String,
+}
+
+/// Generates a new API key with a random id and secret.
+pub fn generate(request: CreateKeyRequest) -> anyhow::Result<CreatedKey> {
+    let mut id = [0u8; 16];
+    let mut secret = [0u8; 32];
+    getrandom::getrandom(&mut id).map_err(|e| anyhow::anyhow!("Failed to generate an API key: {e}"))?;
+    getrandom::getrandom(&mut secret).map_err(|e| anyhow::anyhow!("Failed to generate an API key: {e}"))?;
+
+    Ok(CreatedKey {
+        key: ApiKey {
+            id: uuid::Builder::from_random_bytes(id).into_uuid().to_string(),
+            name: request.name,
+            scopes: request.scopes,
+            tenant_id: request.tenant_id,
+            created_at: Utc::now(),
+            revoked_at: None,
+        },
+        secret: format!("{PREFIX}{}", URL_SAFE_NO_PAD.encode(secret)),
+    })
+}
+
+/// The hex-encoded SHA-256 hash of a secret key, which is what gets stored.
+pub fn hash_of(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_secrets_are_unique_and_prefixed() {
+        let request = CreateKeyRequest {
+            name: "ingestion".to_string(),
+            scopes: vec![Scope::ImportWrite],
+            tenant_id: None,
+        };
+
+        let first = generate(request.clone()).unwrap();
+        let second = generate(request).unwrap();
+
+        assert!(first.secret.starts_with(PREFIX));
+        assert_ne!(first.secret, second.secret);
+        assert_ne!(first.key.id, second.key.id);
+        assert_ne!(first.secret, hash_of(&first.secret));
+    }
+}
@@ -1,15 +1,26 @@
 mod api;
+mod auth;
 mod db;
 mod commands;
+mod errors;
+mod health;
 mod http;
+mod identity;
 mod import;
+mod keys;
+mod metrics;
 mod options;
+mod persisted;
+mod publish;
+mod ratelimit;
+mod schema;
 mod verbose;
+mod watch;
 
 use clap::Parser;
 use options::Options;
 use verbose::set_log_level;
-use crate::options::Commands;
+use crate::options::{Commands, KeysCommands, SchemaCommands};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -22,5 +33,16 @@ async fn main() -> anyhow::Result<()> {
     match options.command {
         Commands::Import(import_options) => commands::import_assets(&import_options).await,
         Commands::Start(start_options) => http::start_server(&start_options).await,
+        Commands::Merge(merge_options) => commands::merge_identities(&merge_options).await,
+        Commands::Split(split_options) => commands::split_identity(&split_options).await,
+        Commands::Schema(schema_options) => match schema_options.command {
+            SchemaCommands::Print => commands::print_schema(),
+            SchemaCommands::Diff(diff_options) => commands::diff_schema(&diff_options).await,
+        },
+        Commands::Keys(keys_options) => match keys_options.command {
+            KeysCommands::Create(create_options) => commands::create_key(&create_options).await,
+            KeysCommands::List(server_options) => commands::list_keys(&server_options).await,
+            KeysCommands::Revoke(revoke_options) => commands::revoke_key(&revoke_options).await,
+        },
     }
 }
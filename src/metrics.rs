@@ -0,0 +1,257 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextExecute};
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use prometheus::{
+    DEFAULT_BUCKETS, Histogram, HistogramOpts, HistogramTimer, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder, exponential_buckets,
+};
+
+use crate::db::AssetRepository;
+use crate::import::ImportReport;
+
+/// Every metric of the server, registered once and shared by all requests.
+struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    graphql_duration: HistogramVec,
+    query_duration: HistogramVec,
+    pool_connections: IntGaugeVec,
+    pool_wait: Histogram,
+    import_rows: IntCounterVec,
+    import_failures: IntCounter,
+    database_size: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let metrics = Self {
+            registry: Registry::new_custom(Some("assets".to_string()), None)?,
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests by route, method and status."),
+                &["route", "method", "status"],
+            )?,
+            http_duration: HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route, method and status.")
+                    .buckets(DEFAULT_BUCKETS.to_vec()),
+                &["route", "method", "status"],
+            )?,
+            graphql_duration: HistogramVec::new(
+                HistogramOpts::new("graphql_operation_duration_seconds", "GraphQL operation durations by name and outcome.")
+                    .buckets(DEFAULT_BUCKETS.to_vec()),
+                &["operation", "outcome"],
+            )?,
+            query_duration: HistogramVec::new(
+                HistogramOpts::new("db_query_duration_seconds", "DuckDB query durations by repository method.")
+                    .buckets(DEFAULT_BUCKETS.to_vec()),
+                &["method"],
+            )?,
+            pool_connections: IntGaugeVec::new(
+                Opts::new("db_pool_connections", "Connections of the database pool by state."),
+                &["state"],
+            )?,
+            pool_wait: Histogram::with_opts(
+                HistogramOpts::new("db_pool_wait_seconds", "Time spent waiting for a connection from the database pool.")
+                    .buckets(exponential_buckets(0.0001, 4.0, 9)?),
+            )?,
+            import_rows: IntCounterVec::new(
+                Opts::new("import_rows_total", "Imported asset rows by outcome."),
+                &["outcome"],
+            )?,
+            import_failures: IntCounter::new("import_failures_total", "Imports that failed to be recorded.")?,
+            database_size: IntGauge::new("db_file_size_bytes", "Size of the database file and its write-ahead log.")?,
+        };
+
+        metrics.registry.register(Box::new(metrics.http_requests.clone()))?;
+        metrics.registry.register(Box::new(metrics.http_duration.clone()))?;
+        metrics.registry.register(Box::new(metrics.graphql_duration.clone()))?;
+        metrics.registry.register(Box::new(metrics.query_duration.clone()))?;
+        metrics.registry.register(Box::new(metrics.pool_connections.clone()))?;
+        metrics.registry.register(Box::new(metrics.pool_wait.clone()))?;
+        metrics.registry.register(Box::new(metrics.import_rows.clone()))?;
+        metrics.registry.register(Box::new(metrics.import_failures.clone()))?;
+        metrics.registry.register(Box::new(metrics.database_size.clone()))?;
+
+        Ok(metrics)
+    }
+}
+
+// The metrics are static, so they can only fail to register when two have the same name.
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| Metrics::new().expect("failed to register metrics"));
+
+/// Renders every metric in the Prometheus text format. Pool and file size gauges are sampled now.
+pub fn render(asset_repository: &AssetRepository, database_path: &Path) -> prometheus::Result<String> {
+    let state = asset_repository.pool_state();
+    let idle = i64::from(state.idle_connections);
+    METRICS.pool_connections.with_label_values(&["idle"]).set(idle);
+    METRICS.pool_connections.with_label_values(&["in_use"]).set(i64::from(state.connections) - idle);
+
+    let mut wal = database_path.as_os_str().to_os_string();
+    wal.push(".wal");
+    let size = [database_path, Path::new(&wal)]
+        .into_iter()
+        .filter_map(|file| std::fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum::<u64>();
+    METRICS.database_size.set(size as i64);
+
+    TextEncoder::new().encode_to_string(&METRICS.registry.gather())
+}
+
+/// Counts and times HTTP requests. Requests are labeled by their route rather than their path, so ids in paths
+/// don't create a series per id.
+pub async fn track(matched_path: Option<MatchedPath>, request: Request, next: Next) -> Response {
+    let route = matched_path.as_ref().map_or("unmatched", MatchedPath::as_str).to_string();
+    let method = request.method().to_string();
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    let labels = [route.as_str(), method.as_str(), status.as_str()];
+    METRICS.http_requests.with_label_values(&labels).inc();
+    METRICS.http_duration.with_label_values(&labels).observe(started.elapsed().as_secs_f64());
+
+    response
+}
+
+/// Times a repository method until the returned timer is dropped.
+pub fn time_query(method: &str) -> HistogramTimer {
+    METRICS.query_duration.with_label_values(&[method]).start_timer()
+}
+
+/// Records how long it took to get a connection from the pool.
+pub fn observe_pool_wait(wait: Duration) {
+    METRICS.pool_wait.observe(wait.as_secs_f64());
+}
+
+/// Counts the rows of an import by outcome.
+pub fn record_import(report: &ImportReport) {
+    METRICS.import_rows.with_label_values(&["inserted"]).inc_by(report.inserted.len() as u64);
+    METRICS.import_rows.with_label_values(&["updated"]).inc_by(report.updated.len() as u64);
+    METRICS.import_rows.with_label_values(&["rejected"]).inc_by(report.rejected.len() as u64);
+}
+
+/// Counts an import that failed to be recorded, such as because of a database error.
+pub fn record_import_failure() {
+    METRICS.import_failures.inc();
+}
+
+/// Times GraphQL operations by their name.
+///
+/// Clients choose the names of their operations, so only the first `MAX_OPERATION_NAMES` names seen, besides those
+/// of the persisted query manifest, are used as labels, keeping the number of series bounded. Later names are
+/// labeled `other`, and unnamed operations `anonymous`.
+pub struct GraphqlMetrics {
+    labels: Arc<OperationLabels>,
+}
+
+impl GraphqlMetrics {
+    pub fn new(operations: HashSet<String>) -> Self {
+        Self {
+            labels: Arc::new(OperationLabels::new(operations, MAX_OPERATION_NAMES)),
+        }
+    }
+}
+
+impl ExtensionFactory for GraphqlMetrics {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(GraphqlMetricsExtension(self.labels.clone()))
+    }
+}
+
+struct GraphqlMetricsExtension(Arc<OperationLabels>);
+
+/// How many operation names outside the persisted query manifest are used as labels.
+const MAX_OPERATION_NAMES: usize = 100;
+
+/// The operation names used as labels so far.
+struct OperationLabels {
+    names: Mutex<HashSet<String>>,
+    max_names: usize,
+}
+
+impl OperationLabels {
+    fn new(known: HashSet<String>, max_others: usize) -> Self {
+        Self {
+            max_names: known.len() + max_others,
+            names: Mutex::new(known),
+        }
+    }
+
+    /// The label of an operation: its name while there is room for it, otherwise `other`, or `anonymous` if it has
+    /// no name.
+    fn label<'a>(&self, operation_name: Option<&'a str>) -> &'a str {
+        let Some(name) = operation_name else {
+            return "anonymous";
+        };
+        let mut names = self.names.lock().unwrap_or_else(|e| e.into_inner());
+        if names.contains(name) || (names.len() < self.max_names && names.insert(name.to_string())) {
+            name
+        } else {
+            "other"
+        }
+    }
+}
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for GraphqlMetricsExtension {
+    async fn execute(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        operation_name: Option<&str>,
+        next: NextExecute<'_>,
+    ) -> async_graphql::Response {
+        let started = Instant::now();
+        let response = next.run(ctx, operation_name).await;
+
+        let outcome = if response.is_ok() { "ok" } else { "error" };
+        METRICS
+            .graphql_duration
+            .with_label_values(&[self.0.label(operation_name), outcome])
+            .observe(started.elapsed().as_secs_f64());
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_are_rendered_in_the_text_format() {
+        let directory = std::env::temp_dir().join(format!("assets-{}-metrics", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("assets.db");
+        let repository = AssetRepository::new(&path, 1, Duration::from_secs(1)).unwrap();
+        repository.setup().unwrap();
+        record_import_failure();
+
+        let rendered = render(&repository, &path).unwrap();
+
+        assert!(rendered.contains("assets_import_failures_total"));
+        assert!(rendered.contains("assets_db_pool_connections{state=\"idle\"} 1"));
+        assert!(rendered.contains("assets_db_query_duration_seconds_count{method=\"setup\"}"));
+        assert!(!rendered.contains("assets_db_file_size_bytes 0\n"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn operations_are_labeled_by_name_until_the_limit() {
+        let labels = OperationLabels::new(HashSet::from(["Balances".to_string()]), 1);
+
+        assert_eq!("NetWorth", labels.label(Some("NetWorth")));
+        assert_eq!("other", labels.label(Some("RandomName1234")));
+        assert_eq!("NetWorth", labels.label(Some("NetWorth")));
+        assert_eq!("Balances", labels.label(Some("Balances")));
+        assert_eq!("anonymous", labels.label(None));
+    }
+}
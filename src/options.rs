@@ -1,5 +1,6 @@
 use std::net::SocketAddr;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use crate::db::Scope;
 use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
@@ -18,7 +19,15 @@ pub enum Commands {
     /// Import assets from a JSON file.
     Import(ImportOptions),
     /// Start the Import and GraphQL API server.
-    Start(StartOptions),
+    Start(Box<StartOptions>),
+    /// Merge the history of one asset identity into another.
+    Merge(MergeOptions),
+    /// Split an asset id out of the identity it is grouped under.
+    Split(SplitOptions),
+    /// Print the GraphQL schema or compare it to a saved one.
+    Schema(SchemaOptions),
+    /// Create, list or revoke API keys.
+    Keys(KeysOptions),
 }
 
 #[derive(Debug, Parser)]
@@ -30,6 +39,17 @@ pub struct ImportOptions {
     /// The file path to read the JSON file from. Defaults to STD_IN when a file is not given.
     #[arg(short, long)]
     pub path: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub credentials: Credentials,
+}
+
+/// How the CLI authenticates with the server.
+#[derive(Debug, Args)]
+pub struct Credentials {
+    /// The API key to send with requests.
+    #[arg(long, env = "ASSETS_API_KEY", hide_env_values = true)]
+    pub api_key: Option<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -41,4 +61,265 @@ pub struct StartOptions {
     /// The file path for the DuckDB embedded database file.
     #[arg(short, long, default_value = "assets.db")]
     pub database_path: PathBuf,
+
+    /// Production disables GraphiQL and introspection, hides internal error details and uses stricter limits.
+    #[arg(long, value_enum, default_value_t = Mode::Development)]
+    pub mode: Mode,
+
+    /// Which APIs the server runs. Query instances open the database read-only, so reads can be scaled separately
+    /// from the import instance that owns writes.
+    #[arg(long, value_enum, default_value_t = Role::All)]
+    pub role: Role,
+
+    /// Where to publish a copy of the database after every change, for query instances to open as their database path.
+    #[arg(long, required_if_eq("role", "import"))]
+    pub publish_path: Option<PathBuf>,
+
+    /// The reload endpoint of a query instance to notify after publishing, such as `http://query:2738/reload`.
+    /// Query instances only serve it with authentication enabled, so this requires `--notify-api-key`.
+    /// Can be given multiple times.
+    #[arg(long, requires = "publish_path", requires = "notify_api_key")]
+    pub notify: Vec<reqwest::Url>,
+
+    /// The admin API key to send when notifying query instances, which only serve `/reload` with authentication enabled.
+    #[arg(long, env = "ASSETS_NOTIFY_API_KEY", hide_env_values = true, requires = "notify")]
+    pub notify_api_key: Option<String>,
+
+    /// Reload the database of a query instance whenever its file changes, such as when a nightly build replaces it.
+    #[arg(long)]
+    pub watch: bool,
+
+    /// A file to watch instead of the database, for builds that write it once the new database is in place.
+    #[arg(long, requires = "watch")]
+    pub version_file: Option<PathBuf>,
+
+    /// How often to check the watched file for changes, in seconds.
+    #[arg(long, default_value_t = 5, requires = "watch")]
+    pub watch_interval: u64,
+
+    /// The deepest nesting of fields allowed in a GraphQL operation. Defaults to 12, or 8 in production.
+    #[arg(long)]
+    pub max_depth: Option<usize>,
+
+    /// The highest complexity allowed for a GraphQL operation. Connections cost their page size times the complexity of their nodes.
+    /// Defaults to 2500, or 1000 in production.
+    #[arg(long)]
+    pub max_complexity: Option<usize>,
+
+    /// The largest `first` or `last` a GraphQL connection accepts. Defaults to 100, or 50 in production.
+    #[arg(long)]
+    pub max_page_size: Option<usize>,
+
+    /// The most points a balance or net worth history may have. Defaults to 3660, or 1000 in production.
+    #[arg(long)]
+    pub max_history_points: Option<usize>,
+
+    /// A JSON file mapping SHA-256 hashes to queries. When given, only these queries are executed.
+    /// Otherwise, queries are persisted automatically as clients send them.
+    #[arg(long)]
+    pub persisted_query_manifest: Option<PathBuf>,
+
+    /// A JWKS file path or URL to validate JWT bearer tokens with. When given, every request except GraphiQL
+    /// needs a valid token and is scoped to the tenant of its `cognito_id` or subject claim.
+    #[arg(long)]
+    pub jwks: Option<String>,
+
+    /// The issuer bearer tokens must have been issued by.
+    #[arg(long, requires = "jwks")]
+    pub jwt_issuer: Option<String>,
+
+    /// The audience bearer tokens must have been issued for.
+    #[arg(long, requires = "jwks")]
+    pub jwt_audience: Option<String>,
+
+    /// A scope to grant bearer tokens without a `scope` claim: import:write or assets:read. May be given more
+    /// than once. Tokens are never granted admin, since they are limited to their tenant.
+    #[arg(long = "jwt-scope", default_value = "assets:read")]
+    pub jwt_scopes: Vec<Scope>,
+
+    /// The tenant to assign identities recorded without one, such as those from before tenants were introduced, so
+    /// they stay visible once authentication is enabled. Applied on every start of an instance that owns writes.
+    #[arg(long, env = "ASSETS_DEFAULT_TENANT")]
+    pub default_tenant: Option<String>,
+
+    /// Require an API key on every request except GraphiQL, even without `--jwks`. With `--jwks`,
+    /// requests may send either an API key or a bearer token.
+    #[arg(long)]
+    pub require_auth: bool,
+
+    /// Requests per minute each API key or tenant may send to `/import` and the identity routes. Clients may
+    /// burst up to a minute's worth of requests. Unlimited by default.
+    #[arg(long)]
+    pub import_rate_limit: Option<u32>,
+
+    /// GraphQL requests per minute each API key or tenant may send, separately from imports. Unlimited by default.
+    #[arg(long)]
+    pub graphql_rate_limit: Option<u32>,
+
+    /// Asset rows each API key or tenant may submit for import per UTC day, over `/import` and GraphQL.
+    /// Unlimited by default.
+    #[arg(long)]
+    pub daily_import_quota: Option<u64>,
+
+    /// How long readiness checks wait for a database connection, in milliseconds.
+    #[arg(long, default_value_t = 1000)]
+    pub readiness_timeout: u64,
+
+    /// How long ago the most recent snapshot may have been recorded, in seconds, for the server to be ready.
+    /// Not checked by default.
+    #[arg(long)]
+    pub max_data_age: Option<u64>,
+
+    /// How long to keep serving after a shutdown signal while reporting not ready, in seconds, so load balancers
+    /// stop routing requests before the server stops accepting them.
+    #[arg(long, default_value_t = 0)]
+    pub shutdown_grace_period: u64,
+}
+
+/// How the server is exposed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+    Production,
+    Development,
+}
+
+/// Which APIs a server runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Role {
+    /// GraphQL queries, from a read-only database. The database is reloaded when `--watch` sees it change, or,
+    /// with authentication enabled, when an admin posts to `/reload`.
+    Query,
+    /// Imports, identity changes, API keys, and GraphQL mutations and subscriptions. Query instances given with
+    /// `--notify` must have authentication enabled, and are notified with `--notify-api-key`.
+    Import,
+    /// Everything, from a single read-write database.
+    All,
+}
+
+impl Role {
+    /// Whether a server of this role runs the APIs of another role.
+    pub fn serves(self, role: Role) -> bool {
+        self == Role::All || self == role
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::Query => write!(f, "query"),
+            Role::Import => write!(f, "import"),
+            Role::All => write!(f, "all"),
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct MergeOptions {
+    /// The full URI of the server to POST to for merging identities.
+    #[arg(short, long, default_value = "http://127.0.0.1:2738/identities/merge")]
+    pub uri: reqwest::Url,
+
+    /// The identity to keep.
+    #[arg(short, long)]
+    pub target: String,
+
+    /// The identity whose history is merged into the target.
+    #[arg(short, long)]
+    pub source: String,
+
+    #[command(flatten)]
+    pub credentials: Credentials,
+}
+
+#[derive(Debug, Parser)]
+pub struct SplitOptions {
+    /// The full URI of the server to POST to for splitting identities.
+    #[arg(short, long, default_value = "http://127.0.0.1:2738/identities/split")]
+    pub uri: reqwest::Url,
+
+    /// The asset id to split out into its own identity.
+    #[arg(short, long)]
+    pub asset_id: String,
+
+    #[command(flatten)]
+    pub credentials: Credentials,
+}
+
+#[derive(Debug, Parser)]
+pub struct SchemaOptions {
+    #[command(subcommand)]
+    pub command: SchemaCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SchemaCommands {
+    /// Print the GraphQL schema served by `start` as SDL.
+    Print,
+    /// Classify the changes from a saved schema to the current one. Fails when any change is breaking.
+    Diff(DiffOptions),
+}
+
+#[derive(Debug, Parser)]
+pub struct DiffOptions {
+    /// The file path of the saved SDL to compare against.
+    pub old: PathBuf,
+}
+
+#[derive(Debug, Parser)]
+pub struct KeysOptions {
+    #[command(subcommand)]
+    pub command: KeysCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum KeysCommands {
+    /// Create an API key and print it along with its secret, which is only shown once.
+    Create(CreateKeyOptions),
+    /// List every API key, including revoked ones.
+    List(KeysServerOptions),
+    /// Revoke an API key, so it can no longer be used.
+    Revoke(RevokeKeyOptions),
+}
+
+/// Where API keys are managed. Managing keys requires a key with the `admin` scope when authentication is required.
+#[derive(Debug, Args)]
+pub struct KeysServerOptions {
+    /// The full URI of the server's API keys endpoint.
+    #[arg(short, long, default_value = "http://127.0.0.1:2738/keys")]
+    pub uri: reqwest::Url,
+
+    #[command(flatten)]
+    pub credentials: Credentials,
+}
+
+#[derive(Debug, Parser)]
+pub struct CreateKeyOptions {
+    /// A name describing who uses the key.
+    #[arg(short, long)]
+    pub name: String,
+
+    /// A scope to grant the key: import:write, assets:read or admin. May be given more than once.
+    #[arg(short, long = "scope", required = true)]
+    pub scopes: Vec<Scope>,
+
+    /// Limits the key to the data of a tenant.
+    #[arg(short, long)]
+    pub tenant: Option<String>,
+
+    /// Creates the key directly in a database file instead of through the server, such as the first admin key.
+    /// The server must not be running, since only one process can open the database for writing.
+    #[arg(short, long)]
+    pub database_path: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub server: KeysServerOptions,
+}
+
+#[derive(Debug, Parser)]
+pub struct RevokeKeyOptions {
+    /// The id of the key to revoke.
+    pub id: String,
+
+    #[command(flatten)]
+    pub server: KeysServerOptions,
 }
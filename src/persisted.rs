@@ -0,0 +1,162 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::bail;
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextPrepareRequest};
+use async_graphql::parser::parse_query;
+use async_graphql::{Request, ServerError, ServerResult, from_value};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// The number of automatically persisted queries kept in memory.
+pub const CACHE_SIZE: usize = 1024;
+
+/// The `persistedQuery` extension of a request, as sent by Apollo and Relay clients.
+#[derive(Deserialize)]
+struct PersistedQuery {
+    #[serde(rename = "sha256Hash")]
+    sha256_hash: String,
+}
+
+/// The queries clients may execute in strict mode, keyed by the SHA-256 hash of their text.
+#[derive(Debug, Clone, Default)]
+pub struct Manifest(Arc<HashMap<String, String>>);
+
+impl Manifest {
+    /// Loads a manifest from a JSON file that maps hex-encoded SHA-256 hashes to query text.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let queries: HashMap<String, String> = serde_json::from_str(&contents)?;
+
+        for (hash, query) in &queries {
+            if *hash != hash_of(query) {
+                bail!("The hash {hash} does not match the text of its persisted query");
+            }
+        }
+
+        Ok(Self(Arc::new(queries)))
+    }
+
+    /// The query text for a hash, if the query is in the manifest.
+    pub fn get(&self, hash: &str) -> Option<&str> {
+        self.0.get(hash).map(String::as_str)
+    }
+
+    /// Whether the query text is in the manifest.
+    pub fn contains(&self, query: &str) -> bool {
+        self.0.contains_key(&hash_of(query))
+    }
+
+    /// The names of the operations defined by the queries in the manifest.
+    pub fn operation_names(&self) -> HashSet<String> {
+        self.0
+            .values()
+            .filter_map(|query| parse_query(query).ok())
+            .flat_map(|document| {
+                document
+                    .operations
+                    .iter()
+                    .filter_map(|(name, _)| name.map(|name| name.to_string()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+/// The hex-encoded SHA-256 hash of a query.
+fn hash_of(query: &str) -> String {
+    format!("{:x}", Sha256::digest(query.as_bytes()))
+}
+
+/// Only executes queries from a manifest, either by hash or by their full text.
+pub struct PersistedQueryAllowlist(Manifest);
+
+impl PersistedQueryAllowlist {
+    pub fn new(manifest: Manifest) -> Self {
+        Self(manifest)
+    }
+}
+
+impl ExtensionFactory for PersistedQueryAllowlist {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(AllowlistExtension(self.0.clone()))
+    }
+}
+
+struct AllowlistExtension(Manifest);
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for AllowlistExtension {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        mut request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        let persisted_query = match request.extensions.remove("persistedQuery") {
+            Some(value) => Some(from_value::<PersistedQuery>(value).map_err(|_| {
+                ServerError::new("Invalid \"PersistedQuery\" extension configuration.", None)
+            })?),
+            None => None,
+        };
+
+        if request.query.is_empty() {
+            let query = persisted_query
+                .and_then(|persisted_query| self.0.get(&persisted_query.sha256_hash))
+                .ok_or_else(|| ServerError::new("PersistedQueryNotFound", None))?;
+            request.query = query.to_string();
+        } else if !self.0.contains(&request.query) {
+            return Err(ServerError::new(
+                "Only queries from the persisted query manifest are allowed",
+                None,
+            ));
+        }
+
+        next.run(ctx, request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_graphql::{EmptyMutation, EmptySubscription, Object, Schema};
+
+    struct Query;
+
+    #[Object]
+    impl Query {
+        async fn value(&self) -> i32 {
+            42
+        }
+    }
+
+    #[tokio::test]
+    async fn only_queries_from_the_manifest_are_executed() {
+        let allowed = "{ value }";
+        let named = "query Value { value }";
+        let path = std::env::temp_dir().join(format!("manifest-{}.json", std::process::id()));
+        std::fs::write(&path, serde_json::json!({ hash_of(allowed): allowed, hash_of(named): named }).to_string()).unwrap();
+        let manifest = Manifest::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(HashSet::from(["Value".to_string()]), manifest.operation_names());
+
+        let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+            .extension(PersistedQueryAllowlist::new(manifest))
+            .finish();
+
+        let by_text = schema.execute(allowed).await;
+        assert!(by_text.errors.is_empty());
+
+        let mut by_hash = Request::new("");
+        by_hash.extensions.insert(
+            "persistedQuery".to_string(),
+            async_graphql::Value::from_json(serde_json::json!({ "version": 1, "sha256Hash": hash_of(allowed) })).unwrap(),
+        );
+        let by_hash = schema.execute(by_hash).await;
+        assert!(by_hash.errors.is_empty());
+
+        let other = schema.execute("{ __typename }").await;
+        assert_eq!(1, other.errors.len());
+    }
+}
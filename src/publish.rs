@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+use crate::db::AssetRepository;
+use crate::keys;
+
+/// Publishes copies of the database for query instances to open read-only, and notifies them to reload.
+#[derive(Clone)]
+pub struct Publisher {
+    changed: Arc<Notify>,
+}
+
+impl Publisher {
+    /// Starts publishing in the background, once now and again after every change.
+    pub fn start(
+        repository: AssetRepository,
+        path: PathBuf,
+        query_instances: Vec<reqwest::Url>,
+        api_key: Option<String>,
+    ) -> Self {
+        let changed = Arc::new(Notify::new());
+        let publisher = Self { changed: changed.clone() };
+
+        tokio::spawn(async move {
+            let mut commits = repository.subscribe();
+
+            loop {
+                let copy = repository.clone();
+                let destination = path.clone();
+                match tokio::task::spawn_blocking(move || copy.copy_to(&destination)).await {
+                    Ok(Ok(())) => {
+                        tracing::debug!(path = %path.display(), "Published the database");
+                        notify(&query_instances, api_key.as_deref()).await;
+                    }
+                    Ok(Err(e)) => tracing::error!(%e, "Failed to publish the database"),
+                    Err(e) => tracing::error!(%e, "Failed to publish the database"),
+                }
+
+                tokio::select! {
+                    commit = commits.recv() => if let Err(RecvError::Closed) = commit {
+                        break;
+                    },
+                    _ = changed.notified() => {}
+                }
+                // Commits that arrived while publishing are covered by the next copy.
+                while !matches!(commits.try_recv(), Err(TryRecvError::Empty | TryRecvError::Closed)) {}
+            }
+        });
+
+        publisher
+    }
+
+    /// Publishes again for changes that aren't commits of snapshots, such as identity or API key changes.
+    pub fn changed(&self) {
+        self.changed.notify_one();
+    }
+}
+
+/// Asks each query instance to reload the database. Instances that can't be reached pick up the change the next
+/// time they are notified.
+async fn notify(query_instances: &[reqwest::Url], api_key: Option<&str>) {
+    let client = reqwest::Client::new();
+
+    for uri in query_instances {
+        let mut request = client.post(uri.as_str());
+        if let Some(api_key) = api_key {
+            request = request.header(keys::HEADER, api_key);
+        }
+
+        match request.send().await.and_then(|response| response.error_for_status()) {
+            Ok(_) => tracing::debug!(%uri, "Notified query instance"),
+            Err(e) => tracing::warn!(%e, %uri, "Failed to notify query instance"),
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::{Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::auth::Principal;
+
+/// The groups of routes that are limited separately, so heavy imports don't use up the budget for reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Route {
+    /// `/import` and the identity routes.
+    Import,
+    /// GraphQL operations and subscription upgrades.
+    Graphql,
+}
+
+/// The limits applied to every client. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimits {
+    /// Import requests per minute.
+    pub import_per_minute: Option<u32>,
+    /// GraphQL requests per minute.
+    pub graphql_per_minute: Option<u32>,
+    /// Asset rows that may be submitted for import per UTC day.
+    pub daily_import_rows: Option<u64>,
+}
+
+impl RateLimits {
+    fn per_minute(&self, route: Route) -> Option<u32> {
+        match route {
+            Route::Import => self.import_per_minute,
+            Route::Graphql => self.graphql_per_minute,
+        }
+    }
+}
+
+/// A bucket that holds up to a minute's worth of requests and refills continuously, so clients can burst
+/// up to their limit and are then held to its average rate.
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(per_minute: u32, now: Instant) -> Self {
+        Self {
+            tokens: per_minute as f64,
+            updated: now,
+        }
+    }
+
+    /// Takes a token, or returns how long until one is available.
+    fn take(&mut self, per_minute: u32, now: Instant) -> Result<(), Duration> {
+        let per_second = per_minute as f64 / 60.0;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * per_second).min(per_minute as f64);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / per_second))
+        }
+    }
+}
+
+/// The rows a client has reserved for import on a UTC day.
+#[derive(Debug)]
+struct DailyRows {
+    day: NaiveDate,
+    rows: u64,
+}
+
+/// Enforces the rate limits and import quotas of each client. Clients are tracked in memory, so limits reset
+/// when the server restarts and are not shared between servers.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    buckets: Arc<Mutex<HashMap<(Route, String), TokenBucket>>>,
+    imports: Arc<Mutex<HashMap<String, DailyRows>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Counts a request by a client against the limit of a route.
+    pub fn check(&self, route: Route, client: &str, now: Instant) -> Result<(), Throttled> {
+        let Some(per_minute) = self.limits.per_minute(route).filter(|per_minute| *per_minute > 0) else {
+            return Ok(());
+        };
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        buckets
+            .entry((route, client.to_string()))
+            .or_insert_with(|| TokenBucket::full(per_minute, now))
+            .take(per_minute, now)
+            .map_err(|retry_after| Throttled {
+                retry_after,
+                message: format!("Rate limit of {per_minute} requests per minute exceeded"),
+            })
+    }
+
+    /// Reserves rows submitted for import by a client against its daily quota. Requests that would exceed the
+    /// quota are rejected as a whole, and their rows are not counted.
+    ///
+    /// Rows are reserved before they are imported, so concurrent imports can't exceed the quota together.
+    /// Rows that end up not being recorded are given back with `settle_rows`.
+    pub fn reserve_rows(&self, client: &str, rows: usize, now: DateTime<Utc>) -> Result<Reservation, Throttled> {
+        let Some(quota) = self.limits.daily_import_rows else {
+            return Ok(Reservation { day: None, rows });
+        };
+
+        let today = now.date_naive();
+        let mut imports = self.imports.lock().unwrap_or_else(|e| e.into_inner());
+        let imported = imports.entry(client.to_string()).or_insert(DailyRows { day: today, rows: 0 });
+        if imported.day != today {
+            *imported = DailyRows { day: today, rows: 0 };
+        }
+
+        if imported.rows + rows as u64 > quota {
+            let tomorrow = today.succ_opt().unwrap_or(today).and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+            return Err(Throttled {
+                retry_after: (tomorrow - now).to_std().unwrap_or_default(),
+                message: format!(
+                    "Daily import quota of {quota} rows exceeded, {} rows remain today",
+                    quota.saturating_sub(imported.rows)
+                ),
+            });
+        }
+
+        imported.rows += rows as u64;
+        Ok(Reservation { day: Some(today), rows })
+    }
+
+    /// Only counts the reserved rows that were recorded, giving back those that were rejected or failed to import.
+    /// Rows reserved on an earlier day are not given back, since that day's quota no longer applies.
+    pub fn settle_rows(&self, client: &str, reservation: Reservation, recorded: usize) {
+        let Some(day) = reservation.day else {
+            return;
+        };
+
+        let mut imports = self.imports.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(imported) = imports.get_mut(client).filter(|imported| imported.day == day) {
+            imported.rows = imported.rows.saturating_sub(reservation.rows.saturating_sub(recorded) as u64);
+        }
+    }
+
+    /// The import quota of a client, for imports that don't go through the import route.
+    pub fn quota(&self, client: String) -> ImportQuota {
+        ImportQuota {
+            limiter: self.clone(),
+            client,
+        }
+    }
+
+    /// The middleware state that limits a route.
+    pub fn route(&self, route: Route) -> LimitedRoute {
+        LimitedRoute {
+            limiter: self.clone(),
+            route,
+        }
+    }
+}
+
+/// Rows reserved against the import quota of the day they were reserved on, if there is a quota.
+#[derive(Debug)]
+#[must_use]
+pub struct Reservation {
+    day: Option<NaiveDate>,
+    rows: usize,
+}
+
+/// The key limits are tracked by: the API key of the request, otherwise its tenant. Requests without either
+/// share a single limit, since they are only possible when authentication is disabled.
+pub fn client_of(principal: Option<&Principal>) -> String {
+    match principal {
+        Some(Principal { key_id: Some(key_id), .. }) => format!("key:{key_id}"),
+        Some(Principal { tenant: Some(tenant), .. }) => format!("tenant:{tenant}"),
+        _ => "anonymous".to_string(),
+    }
+}
+
+/// The daily import quota of the client a GraphQL request was made by.
+#[derive(Clone)]
+pub struct ImportQuota {
+    limiter: RateLimiter,
+    client: String,
+}
+
+impl ImportQuota {
+    pub fn reserve(&self, rows: usize) -> Result<Reservation, Throttled> {
+        self.limiter.reserve_rows(&self.client, rows, Utc::now())
+    }
+
+    pub fn settle(&self, reservation: Reservation, recorded: usize) {
+        self.limiter.settle_rows(&self.client, reservation, recorded)
+    }
+}
+
+/// A request that was rejected by a rate limit or quota.
+#[derive(Debug)]
+pub struct Throttled {
+    pub retry_after: Duration,
+    pub message: String,
+}
+
+impl Throttled {
+    /// Retry-After is in whole seconds, so round up to not invite retries that are still too early.
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0)
+    }
+}
+
+impl IntoResponse for Throttled {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, self.retry_after_secs().to_string())],
+            axum::Json(vec![self.message]),
+        )
+            .into_response()
+    }
+}
+
+/// The rate limiter of one route group.
+#[derive(Clone)]
+pub struct LimitedRoute {
+    limiter: RateLimiter,
+    route: Route,
+}
+
+/// Rejects requests over the rate limit of their client with `429 Too Many Requests`. Runs after authentication,
+/// so requests are limited by the principal they were authenticated as.
+pub async fn limit(State(limited): State<LimitedRoute>, request: Request, next: Next) -> Response {
+    let client = client_of(request.extensions().get::<Principal>());
+
+    match limited.limiter.check(limited.route, &client, Instant::now()) {
+        Ok(()) => next.run(request).await,
+        Err(throttled) => {
+            tracing::debug!(%client, route = ?limited.route, "Throttled request");
+            throttled.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn buckets_allow_bursts_and_refill_over_time() {
+        let limiter = RateLimiter::new(RateLimits {
+            import_per_minute: Some(2),
+            ..RateLimits::default()
+        });
+        let now = Instant::now();
+
+        assert!(limiter.check(Route::Import, "a", now).is_ok());
+        assert!(limiter.check(Route::Import, "a", now).is_ok());
+        let throttled = limiter.check(Route::Import, "a", now).unwrap_err();
+        assert_eq!(Duration::from_secs(30), throttled.retry_after);
+
+        // Other clients and routes have their own limits.
+        assert!(limiter.check(Route::Import, "b", now).is_ok());
+        assert!(limiter.check(Route::Graphql, "a", now).is_ok());
+
+        assert!(limiter.check(Route::Import, "a", now + Duration::from_secs(30)).is_ok());
+        assert!(limiter.check(Route::Import, "a", now + Duration::from_secs(30)).is_err());
+    }
+
+    #[test]
+    fn import_quotas_reset_daily() {
+        let limiter = RateLimiter::new(RateLimits {
+            daily_import_rows: Some(10),
+            ..RateLimits::default()
+        });
+        let now = Utc.with_ymd_and_hms(2025, 1, 1, 18, 0, 0).unwrap();
+
+        assert!(limiter.reserve_rows("a", 6, now).is_ok());
+        let throttled = limiter.reserve_rows("a", 6, now).unwrap_err();
+        assert_eq!(Duration::from_secs(6 * 60 * 60), throttled.retry_after);
+        assert!(limiter.reserve_rows("a", 4, now).is_ok());
+        assert!(limiter.reserve_rows("b", 10, now).is_ok());
+
+        assert!(limiter.reserve_rows("a", 10, now + chrono::Duration::hours(6)).is_ok());
+    }
+
+    #[test]
+    fn only_recorded_rows_count_against_the_quota() {
+        let limiter = RateLimiter::new(RateLimits {
+            daily_import_rows: Some(10),
+            ..RateLimits::default()
+        });
+        let now = Utc.with_ymd_and_hms(2025, 1, 1, 18, 0, 0).unwrap();
+
+        let reservation = limiter.reserve_rows("a", 10, now).unwrap();
+        limiter.settle_rows("a", reservation, 6);
+        assert!(limiter.reserve_rows("a", 4, now).is_ok());
+        assert!(limiter.reserve_rows("a", 1, now).is_err());
+
+        // Settling yesterday's reservation doesn't free up today's quota.
+        let yesterday = limiter.reserve_rows("b", 10, now).unwrap();
+        let tomorrow = now + chrono::Duration::hours(12);
+        let _ = limiter.reserve_rows("b", 10, tomorrow).unwrap();
+        limiter.settle_rows("b", yesterday, 0);
+        assert!(limiter.reserve_rows("b", 1, tomorrow).is_err());
+    }
+}
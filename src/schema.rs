@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use async_graphql::parser::parse_schema;
+use async_graphql::parser::types::{
+    BaseType, FieldDefinition, InputValueDefinition, ServiceDocument, Type, TypeDefinition, TypeKind,
+    TypeSystemDefinition,
+};
+use async_graphql::Positioned;
+
+/// How a schema change affects existing clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Existing operations may stop working.
+    Breaking,
+    /// Existing operations keep working, but may see values or types they don't expect.
+    Dangerous,
+    Safe,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Breaking => write!(f, "BREAKING"),
+            Severity::Dangerous => write!(f, "DANGEROUS"),
+            Severity::Safe => write!(f, "SAFE"),
+        }
+    }
+}
+
+/// A single difference between two schemas.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Change {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Change {
+    fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:<9}  {}", self.severity, self.message)
+    }
+}
+
+/// The changes from the old to the new SDL, most severe first.
+pub fn diff(old: &str, new: &str) -> anyhow::Result<Vec<Change>> {
+    let (old, new) = (parse_schema(old)?, parse_schema(new)?);
+    let (old, new) = (types_of(&old), types_of(&new));
+    let mut changes = Vec::new();
+
+    for (name, old_type) in &old {
+        match new.get(name) {
+            None => changes.push(Change::new(Severity::Breaking, format!("Type `{name}` was removed"))),
+            Some(new_type) => diff_type(name, old_type, new_type, &mut changes),
+        }
+    }
+    for name in new.keys().filter(|name| !old.contains_key(*name)) {
+        changes.push(Change::new(Severity::Safe, format!("Type `{name}` was added")));
+    }
+
+    changes.sort_by(|a, b| a.severity.cmp(&b.severity).then_with(|| a.message.cmp(&b.message)));
+    Ok(changes)
+}
+
+fn types_of(document: &ServiceDocument) -> HashMap<&str, &TypeDefinition> {
+    document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            TypeSystemDefinition::Type(definition) => Some((definition.node.name.node.as_str(), &definition.node)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn diff_type(name: &str, old: &TypeDefinition, new: &TypeDefinition, changes: &mut Vec<Change>) {
+    match (&old.kind, &new.kind) {
+        (TypeKind::Scalar, TypeKind::Scalar) => {}
+        (TypeKind::Object(old), TypeKind::Object(new)) => {
+            diff_names(name, "interface", &old.implements, &new.implements, changes);
+            diff_fields(name, &old.fields, &new.fields, changes);
+        }
+        (TypeKind::Interface(old), TypeKind::Interface(new)) => {
+            diff_names(name, "interface", &old.implements, &new.implements, changes);
+            diff_fields(name, &old.fields, &new.fields, changes);
+        }
+        (TypeKind::Union(old), TypeKind::Union(new)) => {
+            diff_names(name, "member", &old.members, &new.members, changes);
+        }
+        (TypeKind::Enum(old), TypeKind::Enum(new)) => {
+            let old_values: Vec<_> = old.values.iter().map(|value| value.node.value.clone()).collect();
+            let new_values: Vec<_> = new.values.iter().map(|value| value.node.value.clone()).collect();
+            diff_names(name, "value", &old_values, &new_values, changes);
+        }
+        (TypeKind::InputObject(old), TypeKind::InputObject(new)) => {
+            diff_inputs(name, "Input field", &old.fields, &new.fields, changes);
+        }
+        _ => changes.push(Change::new(
+            Severity::Breaking,
+            format!("Type `{name}` changed from {} to {}", kind_of(&old.kind), kind_of(&new.kind)),
+        )),
+    }
+}
+
+fn kind_of(kind: &TypeKind) -> &'static str {
+    match kind {
+        TypeKind::Scalar => "a scalar",
+        TypeKind::Object(_) => "an object",
+        TypeKind::Interface(_) => "an interface",
+        TypeKind::Union(_) => "a union",
+        TypeKind::Enum(_) => "an enum",
+        TypeKind::InputObject(_) => "an input object",
+    }
+}
+
+/// Compares interfaces, union members or enum values. Removing one breaks clients,
+/// while adding one may surprise clients that handle every known case.
+fn diff_names<T: Display + PartialEq>(
+    name: &str,
+    what: &str,
+    old: &[Positioned<T>],
+    new: &[Positioned<T>],
+    changes: &mut Vec<Change>,
+) {
+    for value in old.iter().filter(|value| !new.contains(value)) {
+        changes.push(Change::new(Severity::Breaking, format!("The {what} `{value}` was removed from `{name}`")));
+    }
+    for value in new.iter().filter(|value| !old.contains(value)) {
+        changes.push(Change::new(Severity::Dangerous, format!("The {what} `{value}` was added to `{name}`")));
+    }
+}
+
+fn diff_fields(
+    name: &str,
+    old: &[Positioned<FieldDefinition>],
+    new: &[Positioned<FieldDefinition>],
+    changes: &mut Vec<Change>,
+) {
+    for old_field in old {
+        let field = format!("{name}.{}", old_field.node.name.node);
+        let Some(new_field) = new.iter().find(|new_field| new_field.node.name.node == old_field.node.name.node) else {
+            changes.push(Change::new(Severity::Breaking, format!("Field `{field}` was removed")));
+            continue;
+        };
+
+        let (old_type, new_type) = (&old_field.node.ty.node, &new_field.node.ty.node);
+        if old_type != new_type {
+            let severity = if is_safe_output_change(old_type, new_type) {
+                Severity::Safe
+            } else {
+                Severity::Breaking
+            };
+            changes.push(Change::new(
+                severity,
+                format!("Field `{field}` changed type from `{old_type}` to `{new_type}`"),
+            ));
+        }
+
+        diff_inputs(&field, "Argument", &old_field.node.arguments, &new_field.node.arguments, changes);
+    }
+
+    for new_field in new.iter().filter(|new_field| {
+        !old.iter().any(|old_field| old_field.node.name.node == new_field.node.name.node)
+    }) {
+        changes.push(Change::new(
+            Severity::Safe,
+            format!("Field `{name}.{}` was added", new_field.node.name.node),
+        ));
+    }
+}
+
+/// Compares arguments or input object fields, which clients send rather than receive.
+fn diff_inputs(
+    name: &str,
+    what: &str,
+    old: &[Positioned<InputValueDefinition>],
+    new: &[Positioned<InputValueDefinition>],
+    changes: &mut Vec<Change>,
+) {
+    for old_input in old {
+        let input = format!("{name}.{}", old_input.node.name.node);
+        let Some(new_input) = new.iter().find(|new_input| new_input.node.name.node == old_input.node.name.node) else {
+            changes.push(Change::new(Severity::Breaking, format!("{what} `{input}` was removed")));
+            continue;
+        };
+
+        let (old_type, new_type) = (&old_input.node.ty.node, &new_input.node.ty.node);
+        if old_type != new_type {
+            let severity = if is_safe_input_change(old_type, new_type) {
+                Severity::Safe
+            } else {
+                Severity::Breaking
+            };
+            changes.push(Change::new(
+                severity,
+                format!("{what} `{input}` changed type from `{old_type}` to `{new_type}`"),
+            ));
+        }
+
+        let old_default = old_input.node.default_value.as_ref().map(|value| &value.node);
+        let new_default = new_input.node.default_value.as_ref().map(|value| &value.node);
+        if old_default != new_default {
+            changes.push(Change::new(Severity::Dangerous, format!("{what} `{input}` changed its default value")));
+        }
+    }
+
+    for new_input in new.iter().filter(|new_input| {
+        !old.iter().any(|old_input| old_input.node.name.node == new_input.node.name.node)
+    }) {
+        let input = format!("{name}.{}", new_input.node.name.node);
+        if new_input.node.ty.node.nullable || new_input.node.default_value.is_some() {
+            changes.push(Change::new(Severity::Dangerous, format!("Optional {} `{input}` was added", what.to_lowercase())));
+        } else {
+            changes.push(Change::new(Severity::Breaking, format!("Required {} `{input}` was added", what.to_lowercase())));
+        }
+    }
+}
+
+/// Whether clients reading a value of the old type can read the new type, which may only be stricter about nulls.
+fn is_safe_output_change(old: &Type, new: &Type) -> bool {
+    (old.nullable || !new.nullable) && is_same_base(&old.base, &new.base, is_safe_output_change)
+}
+
+/// Whether values clients send for the old type are accepted for the new type, which may only be more lenient about nulls.
+fn is_safe_input_change(old: &Type, new: &Type) -> bool {
+    (!old.nullable || new.nullable) && is_same_base(&old.base, &new.base, is_safe_input_change)
+}
+
+fn is_same_base(old: &BaseType, new: &BaseType, is_safe: fn(&Type, &Type) -> bool) -> bool {
+    match (old, new) {
+        (BaseType::Named(old), BaseType::Named(new)) => old == new,
+        (BaseType::List(old), BaseType::List(new)) => is_safe(old, new),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = r#"
+        enum Category { CASH INVESTMENT }
+        input Filter { nickname: String, minBalance: Float }
+        type Asset { nickname: String, balance: Float!, category: Category! }
+        type Query { assets(filter: Filter, first: Int): [Asset!]! }
+    "#;
+
+    fn severities(new: &str) -> Vec<(Severity, String)> {
+        diff(OLD, new)
+            .unwrap()
+            .into_iter()
+            .map(|change| (change.severity, change.message))
+            .collect()
+    }
+
+    #[test]
+    fn identical_schemas_have_no_changes() {
+        assert!(severities(OLD).is_empty());
+    }
+
+    #[test]
+    fn classifies_changes_by_their_effect_on_clients() {
+        let new = r#"
+            enum Category { CASH INVESTMENT CRYPTO }
+            input Filter { nickname: String, minBalance: Float, isActive: Boolean! }
+            type Asset { nickname: String!, category: Category! }
+            type Query { assets(filter: Filter, first: Int!, last: Int): [Asset!]! }
+            type Mutation { rename(id: ID!): Asset }
+        "#;
+
+        assert_eq!(
+            vec![
+                (Severity::Breaking, "Argument `Query.assets.first` changed type from `Int` to `Int!`".to_string()),
+                (Severity::Breaking, "Field `Asset.balance` was removed".to_string()),
+                (Severity::Breaking, "Required input field `Filter.isActive` was added".to_string()),
+                (Severity::Dangerous, "Optional argument `Query.assets.last` was added".to_string()),
+                (Severity::Dangerous, "The value `CRYPTO` was added to `Category`".to_string()),
+                (Severity::Safe, "Field `Asset.nickname` changed type from `String` to `String!`".to_string()),
+                (Severity::Safe, "Type `Mutation` was added".to_string()),
+            ],
+            severities(new)
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::db::AssetRepository;
+
+/// What identifies a version of a file: its modification time and size.
+type Fingerprint = (SystemTime, u64);
+
+fn fingerprint_of(path: &Path) -> Option<Fingerprint> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Decides when a watched file has changed to a new version that is safe to load.
+///
+/// Files that are copied in place change several times while they are written, so a new version is only
+/// loaded once the file looked the same on two polls in a row.
+#[derive(Debug)]
+struct Versions {
+    loaded: Option<Fingerprint>,
+    pending: Option<Fingerprint>,
+}
+
+impl Versions {
+    fn new(loaded: Option<Fingerprint>) -> Self {
+        Self { loaded, pending: None }
+    }
+
+    /// Records what the file looks like now, returning whether it should be loaded.
+    fn observe(&mut self, current: Option<Fingerprint>) -> bool {
+        let settled = current.is_some() && current == self.pending && current != self.loaded;
+        self.pending = current;
+        settled
+    }
+
+    fn loaded(&mut self, version: Option<Fingerprint>) {
+        self.loaded = version;
+    }
+}
+
+/// Reloads a read-only repository in the background whenever the watched file changes. The watched file is either
+/// the database itself, or a version marker that is written once a new database is in place.
+pub fn start(repository: AssetRepository, watched: PathBuf, interval: Duration) {
+    tokio::spawn(async move {
+        let mut versions = Versions::new(fingerprint_of(&watched));
+        let mut ticks = tokio::time::interval(interval);
+
+        loop {
+            ticks.tick().await;
+
+            let current = fingerprint_of(&watched);
+            if !versions.observe(current) {
+                continue;
+            }
+
+            let reloading = repository.clone();
+            match tokio::task::spawn_blocking(move || reloading.reload()).await {
+                Ok(Ok(())) => tracing::info!(path = %watched.display(), "Reloaded the database"),
+                Ok(Err(e)) => tracing::error!(%e, path = %watched.display(), "Failed to reload the database"),
+                Err(e) => tracing::error!(%e, path = %watched.display(), "Failed to reload the database"),
+            }
+            // Versions that fail to load are skipped, so queries keep using the old pool until the file changes again.
+            versions.loaded(current);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_loaded_once_they_settle() {
+        let first = Some((SystemTime::UNIX_EPOCH, 1));
+        let writing = Some((SystemTime::UNIX_EPOCH + Duration::from_secs(1), 2));
+        let written = Some((SystemTime::UNIX_EPOCH + Duration::from_secs(2), 3));
+        let mut versions = Versions::new(first);
+
+        assert!(!versions.observe(first));
+        assert!(!versions.observe(first));
+        assert!(!versions.observe(writing));
+        assert!(!versions.observe(written));
+        assert!(versions.observe(written));
+        versions.loaded(written);
+        assert!(!versions.observe(written));
+
+        // Missing files are never loaded.
+        assert!(!versions.observe(None));
+        assert!(!versions.observe(None));
+    }
+}